@@ -1,5 +1,6 @@
 use bootloader::{BiosBoot, UefiBoot};
-use std::path::PathBuf;
+use object::{Object, ObjectSymbol, SymbolKind};
+use std::path::{Path, PathBuf};
 
 fn main() {
     // set by cargo, build scripts should use this directory for output files
@@ -10,15 +11,21 @@ fn main() {
         std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").expect("Can't find kernel bin file"),
     );
 
+    // extract the kernel's symbols, loaded as the ramdisk to symbolize backtraces
+    let symbols_path = out_dir.join("symbols.bin");
+    write_symbol_table(&kernel, &symbols_path);
+
     // create a UEFI disk image
     let uefi_path = out_dir.join("uefi.img");
     UefiBoot::new(&kernel)
+        .set_ramdisk(&symbols_path)
         .create_disk_image(&uefi_path)
         .expect("Can't create a bootable UEFI disk image");
 
     // create a BIOS disk image
     let bios_path = out_dir.join("bios.img");
     BiosBoot::new(&kernel)
+        .set_ramdisk(&symbols_path)
         .create_disk_image(&bios_path)
         .expect("Can't create a bootable BIOS disk image");
 
@@ -26,3 +33,49 @@ fn main() {
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
     println!("cargo:rustc-env=BIOS_PATH={}", bios_path.display());
 }
+
+/// Write the demangled function symbols of the `kernel` ELF to `path`, in the format read
+/// by `kernel::backtrace`:
+///
+/// - the `KSYM` magic and the number of symbols as a `u32`,
+/// - for each symbol sorted by address, its address and size as `u64`s, then the offset
+///   and length of its name as `u32`s,
+/// - the names, as UTF-8 without separators.
+///
+/// All integers are little endian.
+fn write_symbol_table(kernel: &Path, path: &Path) {
+    let elf = std::fs::read(kernel).expect("Can't read the kernel bin file");
+    let elf = object::File::parse(&*elf).expect("Can't parse the kernel ELF");
+
+    let mut symbols: Vec<_> = elf
+        .symbols()
+        .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.size() > 0)
+        .filter_map(|symbol| {
+            let name = rustc_demangle::demangle(symbol.name().ok()?);
+
+            // the alternate format omits the hashes
+            Some((symbol.address(), symbol.size(), format!("{name:#}")))
+        })
+        .collect();
+    symbols.sort_unstable_by_key(|&(address, _, _)| address);
+    symbols.dedup_by_key(|&mut (address, _, _)| address);
+
+    let count = u32::try_from(symbols.len()).expect("Too many kernel symbols");
+    let mut table = b"KSYM".to_vec();
+    let mut names = String::new();
+
+    table.extend(count.to_le_bytes());
+    for (address, size, name) in &symbols {
+        let offset = u32::try_from(names.len()).expect("Kernel symbol names are too long");
+        let len = u32::try_from(name.len()).expect("Kernel symbol names are too long");
+
+        table.extend(address.to_le_bytes());
+        table.extend(size.to_le_bytes());
+        table.extend(offset.to_le_bytes());
+        table.extend(len.to_le_bytes());
+        names.push_str(name);
+    }
+    table.extend(names.as_bytes());
+
+    std::fs::write(path, table).expect("Can't write the kernel symbol table");
+}
@@ -1,7 +1,9 @@
-use acpi::{AcpiHandler, AcpiTables, InterruptModel};
+use acpi::{AcpiHandler, AcpiTables, HpetInfo, InterruptModel};
+use x86_64::PhysAddr;
 
-use crate::{interrupts, memory};
+use crate::{drivers, interrupts, memory};
 
+/// Maps each ACPI region in its own kernel virtual range.
 #[derive(Clone)]
 pub struct Handler;
 
@@ -12,69 +14,40 @@ impl AcpiHandler for Handler {
         physical_address: usize,
         size: usize,
     ) -> acpi::PhysicalMapping<Self, T> {
-        use x86_64::{
-            structures::paging::{mapper::MapToError, Page, PageTableFlags, PhysFrame},
-            PhysAddr,
-        };
-
-        let phys_addr = PhysAddr::new(physical_address as u64);
-        let virtual_address = memory::physical_to_virtual(phys_addr);
-
-        let phys_frame = PhysFrame::containing_address(phys_addr);
-        let page = Page::containing_address(virtual_address);
+        // ACPI tables live in RAM, so they're cached like the rest of it
+        let mapping = memory::map_mmio(
+            PhysAddr::new(physical_address as u64),
+            size,
+            memory::CachePolicy::WriteBack,
+        )
+        .expect("Failed to map ACPI region");
+        let virtual_address = mapping.leak();
 
-        #[expect(unsafe_code)]
-        // SAFETY: Addresses don't have interferens with other mappings.
-        match unsafe {
-            memory::map_page(
-                page,
-                phys_frame,
-                PageTableFlags::PRESENT
-                    | PageTableFlags::WRITABLE
-                    | PageTableFlags::NO_CACHE
-                    | PageTableFlags::WRITE_THROUGH,
-            )
-        } {
-            Ok(()) => (),
-            Err(e) => {
-                if matches!(e, MapToError::FrameAllocationFailed) {
-                    panic!("Failed to map page for ACPI (out of memory)")
-                }
-                // Else Skip mapping as page already exists
-            }
-        }
+        let mapped_length = (virtual_address + size.max(1) as u64)
+            .align_up(memory::PAGE_SIZE as u64)
+            - virtual_address.align_down(memory::PAGE_SIZE as u64);
 
-        // FIX: Should we pass aligned page size in mapped_length rather than just the size?
         acpi::PhysicalMapping::new(
             physical_address,
             #[expect(clippy::unwrap_used)]
             core::ptr::NonNull::new(virtual_address.as_mut_ptr()).unwrap(),
             size,
-            size,
+            #[expect(clippy::cast_possible_truncation)]
+            {
+                mapped_length as usize
+            },
             Self,
         )
     }
 
     fn unmap_physical_region<T>(region: &acpi::PhysicalMapping<Self, T>) {
-        use x86_64::{
-            structures::paging::{mapper::UnmapError, Page, Size4KiB},
-            VirtAddr,
-        };
-
-        let region_start = region.virtual_start().addr().get() as u64;
-        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(region_start));
-
-        match memory::unmap_page(page) {
-            Ok(()) => (),
-            Err(e) => {
-                if let UnmapError::InvalidFrameAddress(err) = e {
-                    panic!(
-                        "Failed to unmap page for ACPI, the address attempted to unmap from doesn't exist: {:?}",
-                        err
-                    )
-                }
-                // Else Skip unmap as large page already exists here or page never existed to begin with
-            }
+        #[expect(unsafe_code)]
+        // SAFETY: The region was mapped by `map_physical_region` and is being dropped.
+        unsafe {
+            memory::unmap_mmio(
+                x86_64::VirtAddr::from_ptr(region.virtual_start().as_ptr()),
+                region.region_length(),
+            );
         }
     }
 }
@@ -104,4 +77,9 @@ pub fn init(rsdp_addr: u64) {
             panic!("Unknown interrupt model")
         }
     }
+
+    // The HPET is optional, the PIT is used instead to calibrate timers
+    if let Ok(hpet) = HpetInfo::new(&tables) {
+        drivers::hpet::init(PhysAddr::new(hpet.base_address as u64));
+    }
 }
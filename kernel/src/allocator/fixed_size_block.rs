@@ -1,115 +1,279 @@
-use super::{linked_list, Locked};
+#[cfg(feature = "heap-tracking")]
+use super::tracking;
+use super::{
+    align_up, linked_list, page_backed, slab, BlockStats, HeapStats, Locked, HEAP_GROWTH_STEP,
+};
+use crate::memory;
 use core::alloc::{GlobalAlloc, Layout};
 
-struct ListNode {
-    next: Option<&'static mut ListNode>,
-}
-
 /// The sizes must each be power of 2 because they are also used as
 /// the block alignment (alignments must be always powers of 2).
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+/// Each size is served by its own `slab::Cache`.
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 pub struct Allocator {
-    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    /// One slab cache for each of the `BLOCK_SIZES`.
+    caches: [slab::Cache; BLOCK_SIZES.len()],
+    #[expect(clippy::struct_field_names)]
     fallback_allocator: linked_list::Allocator,
+    /// Serves allocations larger than a page.
+    #[expect(clippy::struct_field_names)]
+    page_allocator: page_backed::Allocator,
+    heap_start: usize,
+    /// End of the mapped part of the heap.
+    heap_end: usize,
+    /// End of the virtual window reserved for the heap.
+    heap_limit: usize,
+    /// Bytes requested by live allocations.
+    bytes_in_use: usize,
+    high_water_mark: usize,
+    #[cfg(feature = "heap-tracking")]
+    tracker: tracking::Tracker,
 }
 
 #[expect(unsafe_code)]
 // SAFETY: Allocated and deallocated memory is valid.
 unsafe impl GlobalAlloc for Locked<Allocator> {
+    // never inlined, so it has its own frame for the allocation tracker to start from
+    #[inline(never)]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // the swap device may run in a page fault raised with the heap locked
+        debug_assert!(!memory::in_swap_io(), "The swap device used the heap");
+
         let mut allocator = self.lock();
 
-        match list_index(&layout) {
-            Some(index) => {
-                if let Some(node) = allocator.list_heads[index].take() {
-                    allocator.list_heads[index] = node.next.take();
-
-                    core::ptr::from_mut::<ListNode>(node).cast::<u8>()
-                } else {
-                    // no block exists in list => allocate new block
-                    let block_size = BLOCK_SIZES[index];
-                    // only works if all block sizes are a power of 2
-                    let block_align = block_size;
-
-                    #[expect(clippy::unwrap_used)]
-                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
-
-                    // NOTE: The block will be added to the block list on deallocation.
-                    // Since it does have a size that is in `BLOCK_SIZES`
-                    allocator.fallback_alloc(layout)
-                }
-            }
-            // FIX: When there is no space avialable it will not retrive blocks from the `list_heads`.
-            // So when we always allocate 8 sized blocks, even if there were empty we can't create
-            // new 128 blocks if there was no avialable space in the fallback allocator.
-            // It might make sense to enforce a maximum list length for each block size.
-            // When the maximum length is reached, subsequent deallocations are freed using
-            // the fallback allocator instead of being added to the list.
-            None => allocator.fallback_alloc(layout),
+        let ptr = allocator.allocate(layout);
+
+        // failures are reported by the allocation error handler, since callers may
+        // handle them
+        if !ptr.is_null() {
+            allocator.bytes_in_use += layout.size();
+            allocator.high_water_mark = allocator.high_water_mark.max(allocator.bytes_in_use);
+
+            #[cfg(feature = "heap-tracking")]
+            allocator
+                .tracker
+                .insert(ptr, layout.size(), tracking::frame_pointer());
         }
+
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
-
-        if let Some(index) = list_index(&layout) {
-            let new_node = ListNode {
-                next: allocator.list_heads[index].take(),
-            };
+        debug_assert!(!memory::in_swap_io(), "The swap device used the heap");
 
-            // verify that block has size and alignment required for storing node
-            assert!(size_of::<ListNode>() <= BLOCK_SIZES[index]);
-            assert!(align_of::<ListNode>() <= BLOCK_SIZES[index]);
+        let mut allocator = self.lock();
 
-            #[expect(clippy::cast_ptr_alignment)]
-            // trust me bro, should be ok
-            let new_node_ptr = ptr.cast::<ListNode>();
+        allocator.deallocate(ptr, layout);
+        allocator.bytes_in_use -= layout.size();
 
-            new_node_ptr.write(new_node);
-            allocator.list_heads[index] = Some(&mut *new_node_ptr);
-        } else {
-            #[expect(clippy::unwrap_used)]
-            let ptr = core::ptr::NonNull::new(ptr).unwrap();
-
-            allocator.fallback_allocator.deallocate(ptr, layout);
-        }
+        #[cfg(feature = "heap-tracking")]
+        allocator.tracker.remove(ptr);
     }
 }
 
 impl Allocator {
     /// Creates an empty ``Allocator``.
     pub const fn new() -> Self {
-        const EMPTY: Option<&'static mut ListNode> = None;
         Self {
-            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            caches: [
+                slab::Cache::new(BLOCK_SIZES[0]),
+                slab::Cache::new(BLOCK_SIZES[1]),
+                slab::Cache::new(BLOCK_SIZES[2]),
+                slab::Cache::new(BLOCK_SIZES[3]),
+                slab::Cache::new(BLOCK_SIZES[4]),
+                slab::Cache::new(BLOCK_SIZES[5]),
+                slab::Cache::new(BLOCK_SIZES[6]),
+                slab::Cache::new(BLOCK_SIZES[7]),
+                slab::Cache::new(BLOCK_SIZES[8]),
+            ],
             fallback_allocator: linked_list::Allocator::new(),
+            page_allocator: page_backed::Allocator::new(),
+            heap_start: 0,
+            heap_end: 0,
+            heap_limit: 0,
+            bytes_in_use: 0,
+            high_water_mark: 0,
+            #[cfg(feature = "heap-tracking")]
+            tracker: tracking::Tracker::new(),
         }
     }
 
     #[expect(unsafe_code)]
     /// Initialize the allocator with the given heap bounds.
     ///
+    /// The heap is grown on demand past `heap_size`, up to `heap_max_size`.
+    /// Allocations larger than a page are mapped separately in their own window,
+    /// starting at `page_backed_start`.
+    ///
     /// # Safety
     ///
     /// This function is unsafe because the caller must guarantee that the given
-    /// heap bounds are valid and that the heap is unused. This method must be
-    /// called only once.
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    /// heap bounds are valid and that the heap is unused. The virtual memory range up
+    /// to `heap_max_size` and the page backed window must also be unused.
+    /// This method must be called only once.
+    pub unsafe fn init(
+        &mut self,
+        heap_start: usize,
+        heap_size: usize,
+        heap_max_size: usize,
+        page_backed_start: usize,
+    ) {
         self.fallback_allocator.init(heap_start, heap_size);
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.heap_limit = heap_start + heap_max_size;
+
+        self.page_allocator
+            .init(page_backed_start, page_backed::PAGE_BACKED_SIZE);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        match list_index(&layout) {
+            Some(index) => {
+                if let Some(block) = self.caches[index].allocate() {
+                    return block;
+                }
+
+                // no free block exists in the cache => add a new slab to it
+                let slab_layout = self.caches[index].slab_layout();
+                let slab = self.fallback_alloc(slab_layout);
+                if slab.is_null() {
+                    return slab;
+                }
+
+                #[expect(unsafe_code)]
+                // SAFETY: The slab was just allocated with the slab layout.
+                unsafe {
+                    self.caches[index].add_slab(slab);
+                }
+
+                self.caches[index]
+                    .allocate()
+                    .expect("A new slab must have free blocks")
+            }
+            None if page_backed::Allocator::is_suitable(&layout) => self
+                .page_allocator
+                .allocate(layout)
+                .unwrap_or_else(|()| self.fallback_alloc(layout)),
+            None => self.fallback_alloc(layout),
+        }
+    }
+
+    #[expect(unsafe_code)]
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        if let Some(index) = list_index(&layout) {
+            if let Some(slab) = self.caches[index].deallocate(ptr) {
+                let slab_layout = self.caches[index].slab_layout();
+
+                #[expect(clippy::unwrap_used)]
+                let slab = core::ptr::NonNull::new(slab).unwrap();
+
+                self.fallback_allocator.deallocate(slab, slab_layout);
+            }
+        } else if self.page_allocator.owns(ptr) {
+            self.page_allocator.deallocate(ptr, layout);
+        } else {
+            #[expect(clippy::unwrap_used)]
+            let ptr = core::ptr::NonNull::new(ptr).unwrap();
+
+            self.fallback_allocator.deallocate(ptr, layout);
+        }
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let (free_regions, free_bytes, largest_free_region) =
+            self.fallback_allocator.free_regions();
+
+        let mut blocks = [BlockStats::default(); BLOCK_SIZES.len()];
+        for (stats, cache) in blocks.iter_mut().zip(&self.caches) {
+            *stats = cache.stats();
+        }
+
+        HeapStats {
+            blocks,
+            bytes_in_use: self.bytes_in_use,
+            high_water_mark: self.high_water_mark,
+            heap_size: self.heap_end - self.heap_start,
+            free_bytes,
+            free_regions,
+            largest_free_region,
+            page_backed_bytes: self.page_allocator.mapped(),
+        }
+    }
+
+    #[cfg(feature = "heap-tracking")]
+    pub const fn tracker(&self) -> &tracking::Tracker {
+        &self.tracker
     }
 
     /// Allocates using the fallback allocator.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        // FIX: Instead of falling back to a linked list allocator, we could have a
-        // special allocator for allocations greater than 4 KiB. The idea is to utilize paging,
-        // which operates on 4 KiB pages, to map a continuous block of virtual memory to
-        // non-continuous physical frames. This way, fragmentation of unused memory is
-        // no longer a problem for large allocations.
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr,
-            Err(()) => core::ptr::null_mut(),
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr;
+        }
+
+        // Out of space => give the empty slabs back and retry
+        if self.reclaim_slabs() {
+            if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+                return ptr;
+            }
+        }
+
+        // Still out of space => map more of the heap window and retry
+        if self.grow(layout) {
+            self.fallback_allocator
+                .allocate_first_fit(layout)
+                .unwrap_or(core::ptr::null_mut())
+        } else {
+            core::ptr::null_mut()
+        }
+    }
+
+    /// Free the empty slabs of all caches using the fallback allocator.
+    ///
+    /// Returns `true` when any slab was freed.
+    fn reclaim_slabs(&mut self) -> bool {
+        let mut reclaimed = false;
+
+        for cache in &mut self.caches {
+            let slab_layout = cache.slab_layout();
+
+            while let Some(slab) = cache.take_empty_slab() {
+                #[expect(clippy::unwrap_used)]
+                let slab = core::ptr::NonNull::new(slab).unwrap();
+
+                self.fallback_allocator.deallocate(slab, slab_layout);
+                reclaimed = true;
+            }
         }
+
+        reclaimed
+    }
+
+    /// Map enough pages at the end of the heap to fit an allocation with the given layout.
+    ///
+    /// Returns `false` when the heap limit is reached or there is no memory left to map.
+    fn grow(&mut self, layout: Layout) -> bool {
+        let size = align_up(
+            linked_list::Allocator::required_region_size(layout),
+            memory::PAGE_SIZE,
+        )
+        .max(HEAP_GROWTH_STEP);
+
+        if size > self.heap_limit - self.heap_end || super::map_pages(self.heap_end, size).is_err()
+        {
+            return false;
+        }
+
+        #[expect(unsafe_code)]
+        // SAFETY: The region was just mapped and is not used by anything else.
+        unsafe {
+            self.fallback_allocator.add_region(self.heap_end, size);
+        }
+        self.heap_end += size;
+
+        true
     }
 }
 
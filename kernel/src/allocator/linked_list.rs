@@ -44,6 +44,39 @@ impl Allocator {
         self.add_free_region(heap_start, heap_size);
     }
 
+    /// Add a new unused memory region to the free list.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the given region is valid and unused.
+    #[expect(unsafe_code)]
+    pub unsafe fn add_region(&mut self, addr: usize, size: usize) {
+        self.add_free_region(addr, size);
+    }
+
+    /// Size of a free region that is guaranteed to fit an allocation with the given layout.
+    pub fn required_region_size(layout: Layout) -> usize {
+        let (size, align) = Self::size_align(layout);
+
+        size + align + 2 * size_of::<ListNode>()
+    }
+
+    /// Returns the number of free regions, their total size and the size of the largest one.
+    pub fn free_regions(&self) -> (usize, usize, usize) {
+        let mut current = &self.head;
+        let (mut count, mut bytes, mut largest) = (0, 0, 0);
+
+        while let Some(ref region) = current.next {
+            count += 1;
+            bytes += region.size;
+            largest = largest.max(region.size);
+
+            current = region;
+        }
+
+        (count, bytes, largest)
+    }
+
     pub fn allocate_first_fit(&mut self, layout: Layout) -> Result<*mut u8, ()> {
         // perform layout adjustments
         let (size, align) = Self::size_align(layout);
@@ -53,7 +86,19 @@ impl Allocator {
                 return Err(());
             };
 
-            let excess_size = region.end_addr() - alloc_end;
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
+
+            let padding_size = alloc_start - region_start;
+            if padding_size > 0 {
+                // add the alignment padding before the allocation back to the free list.
+                #[expect(unsafe_code)]
+                // SAFETY: Values are valid.
+                unsafe {
+                    self.add_free_region(region_start, padding_size);
+                }
+            }
+
+            let excess_size = region_end - alloc_end;
             if excess_size > 0 {
                 // add the excess size of the memory region back to the free list.
                 #[expect(unsafe_code)]
@@ -80,23 +125,52 @@ impl Allocator {
         }
     }
 
-    /// Adds the given memory region to the front of the list.
+    /// Inserts the given memory region in the address ordered list, merging it
+    /// with the adjacent free regions.
     #[expect(unsafe_code)]
-    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+    unsafe fn add_free_region(&mut self, addr: usize, mut size: usize) {
         // ensure that the freed region is capable of holding ListNode
         assert_eq!(align_up(addr, align_of::<ListNode>()), addr);
         assert!(size >= size_of::<ListNode>());
 
-        // create a new list node and append it at the start of the list
+        // find the last region that starts before the freed one
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < addr)
+        {
+            #[expect(clippy::unwrap_used)]
+            let next = current.next.as_mut().unwrap();
+
+            current = next;
+        }
+
+        // merge with the following region
+        if current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() == addr + size)
+        {
+            #[expect(clippy::unwrap_used)]
+            let next = current.next.take().unwrap();
+
+            size += next.size;
+            current.next = next.next.take();
+        }
+
+        // merge with the preceding region (the head is a dummy node of size 0)
+        if current.size > 0 && current.end_addr() == addr {
+            current.size += size;
+            return;
+        }
+
+        // create a new list node and insert it after the preceding region
         let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
+        node.next = current.next.take();
         let node_ptr = addr as *mut ListNode;
         node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr);
-
-        // FIX: Adjacent freed blocks are not merged.
-        // We should keep the linkedlist sorted by the start address to do this.
-        // Or web could just use a binary tree.
+        current.next = Some(&mut *node_ptr);
     }
 
     /// Looks for a free region with the given size and alignment and removes
@@ -137,7 +211,13 @@ impl Allocator {
     ///
     /// Returns the allocation start address on success.
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start > region.start_addr()
+            && alloc_start - region.start_addr() < size_of::<ListNode>()
+        {
+            // padding too small to hold a ListNode, so it could not be freed again
+            alloc_start = align_up(region.start_addr() + size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
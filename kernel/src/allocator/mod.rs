@@ -1,21 +1,184 @@
 mod fixed_size_block;
 mod linked_list;
+mod page_backed;
+mod pool;
+mod queue;
+mod slab;
+#[cfg(feature = "heap-tracking")]
+mod tracking;
 
+use core::alloc::Layout;
+
+use spin::once::Once;
 use x86_64::{
-    structures::paging::{mapper::MapToError, FrameAllocator, Page, PageTableFlags, Size4KiB},
+    structures::paging::{mapper::MapToError, PageSize, PageTableFlags, Size2MiB, Size4KiB},
     VirtAddr,
 };
 
-use crate::memory;
+use crate::{dbg_println, memory};
+
+pub use pool::{BufferPool, PoolBuffer};
+pub use queue::FixedQueue;
 
-pub const HEAP_START: usize = 0x4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB (should be increased when we need more space)
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB mapped at initialization
+/// Size of the virtual window reserved for the heap, which is grown on demand up to it.
+///
+/// Set in MiB by `KERNEL_HEAP_MAX_MIB` at build time, see `.cargo/config.toml`.
+pub const HEAP_MAX_SIZE: usize = parse_mib(option_env!("KERNEL_HEAP_MAX_MIB"), 256) * 1024 * 1024;
+/// Minimum number of bytes mapped at once when growing the heap.
+const HEAP_GROWTH_STEP: usize = 64 * 1024; // 64 KiB
+
+/// Parse a decimal size in MiB, or return `default` when it's not set.
+const fn parse_mib(value: Option<&str>, default: usize) -> usize {
+    let Some(value) = value else {
+        return default;
+    };
+    let digits = value.as_bytes();
+    assert!(!digits.is_empty(), "Heap size must not be empty");
+
+    let mut mib = 0_usize;
+    let mut index = 0;
+    while index < digits.len() {
+        let digit = digits[index];
+        assert!(digit.is_ascii_digit(), "Heap size must be a number of MiB");
+
+        mib = mib * 10 + (digit - b'0') as usize;
+        index += 1;
+    }
+    assert!(mib > 0, "Heap size must not be 0");
+
+    mib
+}
 
 // We shouldn’t perform any allocations in interrupt handlers, since they can run at an arbitrary time and might interrupt an in-progress allocation.
+// They take buffers from `INTERRUPT_POOL` instead.
 #[global_allocator]
 static ALLOCATOR: Locked<fixed_size_block::Allocator> =
     Locked::new(fixed_size_block::Allocator::new());
 
+/// Number and size of the buffers of `INTERRUPT_POOL`, enough for a network packet each.
+const INTERRUPT_POOL_BUFFERS: usize = 64;
+const INTERRUPT_POOL_BUFFER_SIZE: usize = 2048;
+
+static INTERRUPT_POOL: Once<BufferPool> = Once::new();
+
+/// Usage of a single `BLOCK_SIZES` class.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockStats {
+    pub block_size: usize,
+    pub used_blocks: usize,
+    pub slabs: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub blocks: [BlockStats; fixed_size_block::BLOCK_SIZES.len()],
+    /// Bytes requested by live allocations.
+    pub bytes_in_use: usize,
+    /// Highest value `bytes_in_use` has ever reached.
+    pub high_water_mark: usize,
+    /// Bytes of the heap window that are currently mapped.
+    pub heap_size: usize,
+    /// Free bytes of the fallback (linked list) allocator, the number of regions
+    /// they're split into, and the size of the largest one.
+    pub free_bytes: usize,
+    pub free_regions: usize,
+    pub largest_free_region: usize,
+    /// Bytes mapped for allocations larger than a page.
+    pub page_backed_bytes: usize,
+}
+
+impl HeapStats {
+    /// Percentage of the free fallback allocator memory that is outside its largest free region.
+    #[must_use]
+    pub const fn fragmentation(&self) -> usize {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+
+        #[expect(clippy::integer_division)]
+        let largest_percentage = self.largest_free_region * 100 / self.free_bytes;
+
+        100 - largest_percentage
+    }
+}
+
+/// Returns the current usage of the kernel heap.
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// The buffer pool interrupt handlers allocate from, instead of the heap.
+///
+/// # Panics
+///
+/// When the heap wasn't initialized yet.
+pub fn interrupt_pool() -> &'static BufferPool {
+    INTERRUPT_POOL
+        .get()
+        .expect("Interrupt pool wasn't initialized yet")
+}
+
+/// Prints every live heap allocation with its size and callers to serial.
+#[cfg(feature = "heap-tracking")]
+pub fn dump_allocations() {
+    ALLOCATOR.lock().tracker().dump();
+}
+
+/// Called when an allocation of `layout` fails and the caller can't handle it, e.g. with
+/// `Box::new`, instead of `Box::try_new`.
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    report_out_of_memory(layout);
+
+    panic!("Out of memory");
+}
+
+/// Prints the heap and frame allocator usage to serial after an allocation of `layout` failed.
+///
+/// Must be called without holding `ALLOCATOR`.
+fn report_out_of_memory(layout: Layout) {
+    let heap = stats();
+    let frames = memory::frame_stats();
+
+    dbg_println!(
+        "Out of memory: failed to allocate {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+    dbg_println!(
+        "  heap: {} bytes in use (high water mark {}), {} mapped, {} page backed",
+        heap.bytes_in_use,
+        heap.high_water_mark,
+        heap.heap_size,
+        heap.page_backed_bytes
+    );
+    dbg_println!(
+        "  heap free: {} bytes in {} regions, largest {}, {}% fragmented",
+        heap.free_bytes,
+        heap.free_regions,
+        heap.largest_free_region,
+        heap.fragmentation()
+    );
+    for block in heap.blocks.iter().filter(|block| block.slabs > 0) {
+        dbg_println!(
+            "  {}-byte blocks: {} used in {} slabs",
+            block.block_size,
+            block.used_blocks,
+            block.slabs
+        );
+    }
+    dbg_println!(
+        "  frames: {} total, {} free, {} used",
+        frames.total,
+        frames.free,
+        frames.used
+    );
+
+    #[cfg(feature = "heap-tracking")]
+    dump_allocations();
+}
+
 // A wrapper type to impl the Mutex external struct.
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
@@ -43,39 +206,50 @@ const fn align_up(addr: usize, align: usize) -> usize {
 /// # Errors
 ///
 /// When frame allocation or its mapping fails.
+///
+/// # Panics
+///
+/// When the virtual windows of the heap can't be reserved, or the interrupt pool
+/// can't be mapped.
 pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE as u64 - 1_u64;
+    let heap_start = reserve_window(HEAP_MAX_SIZE);
+    let page_backed_start = reserve_window(page_backed::PAGE_BACKED_SIZE);
 
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
+    map_pages(heap_start, HEAP_SIZE)?;
 
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
+    #[expect(unsafe_code)]
+    // SAFETY: Memory ranges are reserved for the heap and this method only called once.
+    unsafe {
+        ALLOCATOR
+            .lock()
+            .init(heap_start, HEAP_SIZE, HEAP_MAX_SIZE, page_backed_start);
+    }
 
-    let frame_allocator = memory::get_memory_frame_allocator();
+    INTERRUPT_POOL.call_once(|| {
+        BufferPool::new(INTERRUPT_POOL_BUFFERS, INTERRUPT_POOL_BUFFER_SIZE)
+            .expect("Failed to map the interrupt pool")
+    });
 
-    for page in page_range {
-        let frame = frame_allocator
-            .lock()
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
+    Ok(())
+}
 
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+/// Reserve a kernel virtual window of `size` bytes, aligned for huge pages.
+#[expect(clippy::cast_possible_truncation)]
+fn reserve_window(size: usize) -> usize {
+    memory::reserve_region(size as u64, Size2MiB::SIZE)
+        .expect("Failed to reserve a virtual window for the heap")
+        .as_u64() as usize
+}
 
-        #[expect(unsafe_code)]
-        // SAFETY: Mapping valid and for unused memory.
-        unsafe {
-            memory::map_page(page, frame, flags)?;
-        };
-    }
+/// Map the pages of `start..start + size` to newly allocated frames.
+///
+/// On failure, the pages mapped so far are unmapped again and their frames freed.
+fn map_pages(start: usize, size: usize) -> Result<(), MapToError<Size4KiB>> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
 
     #[expect(unsafe_code)]
-    // SAFETY: Memory range is unused and this method only called once.
+    // SAFETY: The heap windows are reserved for the allocator and only mapped once.
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        memory::map_anonymous(VirtAddr::new(start as u64), size as u64, flags)
     }
-
-    Ok(())
 }
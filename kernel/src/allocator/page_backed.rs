@@ -0,0 +1,106 @@
+use core::alloc::Layout;
+
+use x86_64::{
+    structures::paging::{PageSize, Size2MiB},
+    VirtAddr,
+};
+
+use super::align_up;
+use crate::memory;
+
+/// Size of the virtual window used for page backed allocations.
+pub const PAGE_BACKED_SIZE: usize = 64 * 1024 * 1024 * 1024; // 64 GiB
+
+/// Allocator for large allocations, that maps a contiguous virtual range to
+/// (possibly non contiguous) physical frames.
+///
+/// Each allocation is followed by an unmapped guard page, so overflowing it faults
+/// instead of corrupting the next allocation.
+pub struct Allocator {
+    /// Free ranges of the virtual window.
+    regions: memory::RegionAllocator,
+    /// Bounds of the virtual window.
+    window_start: usize,
+    window_end: usize,
+    /// Bytes currently mapped for allocations.
+    mapped: usize,
+}
+
+impl Allocator {
+    /// Creates an empty ``Allocator``.
+    pub const fn new() -> Self {
+        Self {
+            regions: memory::RegionAllocator::new(),
+            window_start: 0,
+            window_end: 0,
+            mapped: 0,
+        }
+    }
+
+    /// Initialize the allocator with the given virtual window.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the virtual range is unused. This method must be
+    /// called only once.
+    #[expect(unsafe_code)]
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        self.regions.deallocate(start, size);
+        self.window_start = start;
+        self.window_end = start + size;
+    }
+
+    /// Whether allocations with the given layout should be served by this allocator.
+    pub const fn is_suitable(layout: &Layout) -> bool {
+        layout.size() > memory::PAGE_SIZE && layout.align() <= memory::PAGE_SIZE
+    }
+
+    /// Whether the given pointer was allocated by this allocator.
+    pub fn owns(&self, ptr: *mut u8) -> bool {
+        (self.window_start..self.window_end).contains(&(ptr as usize))
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Result<*mut u8, ()> {
+        let size = align_up(layout.size(), memory::PAGE_SIZE);
+
+        // let large allocations be mapped with 2 MiB pages
+        #[expect(clippy::cast_possible_truncation)]
+        let align = if size as u64 >= Size2MiB::SIZE {
+            Size2MiB::SIZE as usize
+        } else {
+            memory::PAGE_SIZE
+        };
+
+        let start = self
+            .regions
+            .allocate(size + memory::PAGE_SIZE, align)
+            .ok_or(())?;
+
+        if super::map_pages(start, size).is_err() {
+            self.regions.deallocate(start, size + memory::PAGE_SIZE);
+
+            return Err(());
+        }
+
+        self.mapped += size;
+
+        Ok(start as *mut u8)
+    }
+
+    /// Unmap the pages of an allocation, returning its frames to the frame allocator.
+    pub fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let start = ptr as usize;
+        let size = align_up(layout.size(), memory::PAGE_SIZE);
+
+        memory::unmap_range(VirtAddr::new(start as u64), size as u64)
+            .expect("Failed to unmap a page backed allocation");
+
+        self.mapped -= size;
+        self.regions.deallocate(start, size + memory::PAGE_SIZE);
+    }
+
+    /// Bytes currently mapped for allocations.
+    pub const fn mapped(&self) -> usize {
+        self.mapped
+    }
+}
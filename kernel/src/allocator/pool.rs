@@ -0,0 +1,129 @@
+use core::ops::{Deref, DerefMut};
+
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+use super::FixedQueue;
+use crate::memory;
+
+/// A lock-free pool of fixed-size buffers, mapped up front outside of the heap.
+///
+/// Taking and returning buffers never locks or allocates, so interrupt handlers can
+/// use them, e.g. to stage packets or input events for a task.
+pub struct BufferPool {
+    /// Start addresses of the free buffers.
+    free: FixedQueue<usize>,
+    buffer_size: usize,
+    start: VirtAddr,
+    size: u64,
+}
+
+impl BufferPool {
+    /// Map a pool of `count` buffers of `buffer_size` bytes.
+    ///
+    /// Returns `None` when there is no memory or virtual range left.
+    /// It allocates on the heap, so it must not be called from interrupt handlers.
+    #[expect(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn new(count: usize, buffer_size: usize) -> Option<Self> {
+        let buffer_size = buffer_size.max(1).next_multiple_of(size_of::<usize>());
+        let free = FixedQueue::try_new(count.max(1)).ok()?;
+        let size = (count * buffer_size).next_multiple_of(memory::PAGE_SIZE) as u64;
+        let start = memory::reserve_region(size, memory::PAGE_SIZE as u64)?;
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        #[expect(unsafe_code)]
+        // SAFETY: The virtual range was just reserved.
+        if unsafe { memory::map_anonymous(start, size, flags) }.is_err() {
+            #[expect(unsafe_code)]
+            // SAFETY: Nothing is mapped in the range.
+            unsafe {
+                memory::release_region(start, size);
+            }
+
+            return None;
+        }
+
+        #[expect(clippy::cast_possible_truncation)]
+        for index in 0..count {
+            free.push(start.as_u64() as usize + index * buffer_size)
+                .expect("The pool queue fits every buffer");
+        }
+
+        Some(Self {
+            free,
+            buffer_size,
+            start,
+            size,
+        })
+    }
+
+    /// Take a free buffer, holding whatever its previous user left in it.
+    ///
+    /// Returns `None` when every buffer is in use.
+    pub fn take(&self) -> Option<PoolBuffer<'_>> {
+        let addr = self.free.pop()?;
+
+        Some(PoolBuffer { pool: self, addr })
+    }
+
+    #[must_use]
+    pub const fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Number of free buffers.
+    #[must_use]
+    pub fn available(&self) -> usize {
+        self.free.len()
+    }
+}
+
+impl Drop for BufferPool {
+    fn drop(&mut self) {
+        memory::unmap_range(self.start, self.size).expect("Failed to unmap a buffer pool");
+
+        #[expect(unsafe_code)]
+        // SAFETY: Nothing is mapped in the range anymore.
+        unsafe {
+            memory::release_region(self.start, self.size);
+        }
+    }
+}
+
+/// A buffer taken from a `BufferPool`, returned to it when dropped.
+pub struct PoolBuffer<'pool> {
+    pool: &'pool BufferPool,
+    addr: usize,
+}
+
+impl Deref for PoolBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        #[expect(unsafe_code)]
+        // SAFETY: The buffer is mapped and owned by this handle until it's dropped.
+        unsafe {
+            core::slice::from_raw_parts(self.addr as *const u8, self.pool.buffer_size)
+        }
+    }
+}
+
+impl DerefMut for PoolBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        #[expect(unsafe_code)]
+        // SAFETY: The buffer is mapped and owned by this handle until it's dropped.
+        unsafe {
+            core::slice::from_raw_parts_mut(self.addr as *mut u8, self.pool.buffer_size)
+        }
+    }
+}
+
+impl Drop for PoolBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool
+            .free
+            .push(self.addr)
+            .expect("More buffers returned than taken from the pool");
+    }
+}
@@ -0,0 +1,179 @@
+use alloc::boxed::Box;
+use core::{
+    alloc::AllocError,
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+struct Slot<T> {
+    /// Position of the push that may write the slot next, or that position plus 1 once
+    /// the value is written, for the pop at that position.
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A lock-free multi-producer multi-consumer queue of a fixed capacity.
+///
+/// Its slots are allocated up front, so pushing and popping never allocate or lock, and
+/// interrupt handlers can use them.
+pub struct FixedQueue<T> {
+    slots: Box<[Slot<T>]>,
+    /// Positions of the next pop and push, increasing forever.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// SAFETY: Values are moved in and out of the queue, never shared.
+#[expect(unsafe_code)]
+unsafe impl<T: Send> Send for FixedQueue<T> {}
+
+// SAFETY: A slot is only accessed by the push or the pop that claimed its position.
+#[expect(unsafe_code)]
+unsafe impl<T: Send> Sync for FixedQueue<T> {}
+
+impl<T> FixedQueue<T> {
+    /// # Panics
+    ///
+    /// When `capacity` is 0.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "A queue must have room for a value");
+
+        Self::with_slots(Box::new_uninit_slice(capacity))
+    }
+
+    /// Like `new`, but fails instead of aborting when the slots can't be allocated.
+    ///
+    /// # Errors
+    ///
+    /// When the heap is out of memory.
+    ///
+    /// # Panics
+    ///
+    /// When `capacity` is 0.
+    pub fn try_new(capacity: usize) -> Result<Self, AllocError> {
+        assert!(capacity > 0, "A queue must have room for a value");
+
+        Ok(Self::with_slots(Box::try_new_uninit_slice(capacity)?))
+    }
+
+    fn with_slots(mut slots: Box<[MaybeUninit<Slot<T>>]>) -> Self {
+        for (position, slot) in slots.iter_mut().enumerate() {
+            slot.write(Slot {
+                sequence: AtomicUsize::new(position),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            });
+        }
+
+        Self {
+            #[expect(unsafe_code)]
+            // SAFETY: Every slot was just initialized.
+            slots: unsafe { slots.assume_init() },
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Append `value`, or hand it back when the queue is full.
+    ///
+    /// # Errors
+    ///
+    /// When the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[tail % self.slots.len()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            #[expect(clippy::cast_possible_wrap)]
+            match sequence.wrapping_sub(tail) as isize {
+                0 => {
+                    if let Err(current) = self.tail.compare_exchange_weak(
+                        tail,
+                        tail.wrapping_add(1),
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        tail = current;
+                        continue;
+                    }
+
+                    #[expect(unsafe_code)]
+                    // SAFETY: The position was claimed, so nothing else accesses the slot.
+                    unsafe {
+                        (*slot.value.get()).write(value);
+                    }
+                    slot.sequence.store(tail.wrapping_add(1), Ordering::Release);
+
+                    return Ok(());
+                }
+                // the slot still holds the value of the previous lap
+                distance if distance < 0 => return Err(value),
+                // another push claimed the position
+                _ => tail = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Remove the oldest value, or return `None` when the queue is empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut head = self.head.load(Ordering::Relaxed);
+
+        loop {
+            let slot = &self.slots[head % self.slots.len()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            #[expect(clippy::cast_possible_wrap)]
+            match sequence.wrapping_sub(head.wrapping_add(1)) as isize {
+                0 => {
+                    if let Err(current) = self.head.compare_exchange_weak(
+                        head,
+                        head.wrapping_add(1),
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        head = current;
+                        continue;
+                    }
+
+                    #[expect(unsafe_code)]
+                    // SAFETY: The position was claimed, so nothing else accesses the slot.
+                    let value = unsafe { &*slot.value.get() };
+                    #[expect(unsafe_code)]
+                    // SAFETY: The push of the position wrote the value, and it's read once.
+                    let value = unsafe { value.assume_init_read() };
+                    slot.sequence
+                        .store(head.wrapping_add(self.slots.len()), Ordering::Release);
+
+                    return Some(value);
+                }
+                // the value of the position isn't written yet
+                distance if distance < 0 => return None,
+                // another pop claimed the position
+                _ => head = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Number of values in the queue, which may change right away.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        tail.wrapping_sub(head).min(self.slots.len())
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for FixedQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
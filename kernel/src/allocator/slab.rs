@@ -0,0 +1,255 @@
+use core::{alloc::Layout, ptr::NonNull};
+
+use super::{align_up, BlockStats};
+use crate::memory;
+
+/// Maximum number of empty slabs kept in each cache, more are given back to the fallback allocator.
+const MAX_EMPTY_SLABS: usize = 2;
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// Header stored at the start of each slab.
+struct Slab {
+    free_blocks: Option<&'static mut ListNode>,
+    /// Number of blocks handed out from this slab.
+    used: usize,
+    prev: Option<NonNull<Self>>,
+    next: Option<NonNull<Self>>,
+}
+
+/// A cache of equally sized blocks, carved out of slabs of whole pages.
+///
+/// Slabs are aligned to their own size, so the slab of a block is found by masking its address.
+pub struct Cache {
+    block_size: usize,
+    /// Slabs with both used and free blocks, full slabs are not tracked.
+    partial: Option<NonNull<Slab>>,
+    /// Slabs with no used blocks.
+    empty: Option<NonNull<Slab>>,
+    empty_len: usize,
+    /// Number of blocks handed out from all slabs.
+    used_blocks: usize,
+    slabs: usize,
+}
+
+// SAFETY: The slabs are only accessed through the allocator lock.
+#[expect(unsafe_code)]
+unsafe impl Send for Cache {}
+
+impl Cache {
+    /// Creates an empty ``Cache`` for blocks of `block_size` (a power of 2).
+    pub const fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            partial: None,
+            empty: None,
+            empty_len: 0,
+            used_blocks: 0,
+            slabs: 0,
+        }
+    }
+
+    /// Layout of a slab, large enough to hold several blocks after the header.
+    pub fn slab_layout(&self) -> Layout {
+        let size = (self.block_size * 8).max(memory::PAGE_SIZE);
+
+        #[expect(clippy::unwrap_used)]
+        Layout::from_size_align(size, size).unwrap()
+    }
+
+    pub const fn stats(&self) -> BlockStats {
+        BlockStats {
+            block_size: self.block_size,
+            used_blocks: self.used_blocks,
+            slabs: self.slabs,
+        }
+    }
+
+    /// Take a free block, or `None` when a new slab must be added first.
+    pub fn allocate(&mut self) -> Option<*mut u8> {
+        let mut slab_ptr = if let Some(slab_ptr) = self.partial {
+            slab_ptr
+        } else {
+            // reuse an empty slab
+            let slab_ptr = self.empty?;
+
+            #[expect(unsafe_code)]
+            // SAFETY: The slab is linked in the empty list.
+            unsafe {
+                Self::remove(&mut self.empty, slab_ptr);
+            }
+            self.empty_len -= 1;
+
+            #[expect(unsafe_code)]
+            // SAFETY: The slab isn't linked anywhere.
+            unsafe {
+                Self::push(&mut self.partial, slab_ptr);
+            }
+
+            slab_ptr
+        };
+
+        #[expect(unsafe_code)]
+        // SAFETY: Slabs in the partial list are valid and have free blocks.
+        let slab = unsafe { slab_ptr.as_mut() };
+
+        #[expect(clippy::unwrap_used)]
+        let block = slab.free_blocks.take().unwrap();
+        slab.free_blocks = block.next.take();
+        slab.used += 1;
+        self.used_blocks += 1;
+
+        if slab.free_blocks.is_none() {
+            // slab is full now => stop tracking it
+            #[expect(unsafe_code)]
+            // SAFETY: The slab is linked in the partial list.
+            unsafe {
+                Self::remove(&mut self.partial, slab_ptr);
+            }
+        }
+
+        Some(core::ptr::from_mut::<ListNode>(block).cast::<u8>())
+    }
+
+    /// Carve a newly allocated slab into blocks.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to unused memory allocated with `slab_layout`.
+    #[expect(unsafe_code)]
+    pub unsafe fn add_slab(&mut self, ptr: *mut u8) {
+        let slab_size = self.slab_layout().size();
+        let first_block = align_up(size_of::<Slab>(), self.block_size);
+
+        let mut free_blocks = None;
+        for offset in (first_block..slab_size).step_by(self.block_size).rev() {
+            #[expect(clippy::cast_ptr_alignment)]
+            // blocks are aligned to the block size, which is at least the node alignment
+            let node_ptr = ptr.add(offset).cast::<ListNode>();
+
+            node_ptr.write(ListNode { next: free_blocks });
+            free_blocks = Some(&mut *node_ptr);
+        }
+
+        #[expect(clippy::cast_ptr_alignment)]
+        // slabs are aligned to their size
+        let slab_ptr = ptr.cast::<Slab>();
+        slab_ptr.write(Slab {
+            free_blocks,
+            used: 0,
+            prev: None,
+            next: None,
+        });
+
+        #[expect(clippy::unwrap_used)]
+        Self::push(&mut self.empty, NonNull::new(slab_ptr).unwrap());
+        self.empty_len += 1;
+        self.slabs += 1;
+    }
+
+    /// Give a block back to its slab.
+    ///
+    /// Returns a slab that became empty and should be freed by the fallback allocator,
+    /// when the cache already holds `MAX_EMPTY_SLABS`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a block allocated from this cache.
+    #[expect(unsafe_code)]
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8) -> Option<*mut u8> {
+        let slab_addr = ptr as usize & !(self.slab_layout().size() - 1);
+        #[expect(clippy::unwrap_used)]
+        let mut slab_ptr = NonNull::new(slab_addr as *mut Slab).unwrap();
+        let slab = slab_ptr.as_mut();
+
+        let was_full = slab.free_blocks.is_none();
+
+        // verify that block has size and alignment required for storing node
+        assert!(size_of::<ListNode>() <= self.block_size);
+        assert!(align_of::<ListNode>() <= self.block_size);
+
+        #[expect(clippy::cast_ptr_alignment)]
+        // trust me bro, should be ok
+        let node_ptr = ptr.cast::<ListNode>();
+        node_ptr.write(ListNode {
+            next: slab.free_blocks.take(),
+        });
+        slab.free_blocks = Some(&mut *node_ptr);
+        slab.used -= 1;
+        self.used_blocks -= 1;
+
+        if slab.used > 0 {
+            if was_full {
+                Self::push(&mut self.partial, slab_ptr);
+            }
+
+            return None;
+        }
+
+        // slab is empty now
+        if !was_full {
+            Self::remove(&mut self.partial, slab_ptr);
+        }
+
+        if self.empty_len < MAX_EMPTY_SLABS {
+            Self::push(&mut self.empty, slab_ptr);
+            self.empty_len += 1;
+
+            None
+        } else {
+            self.slabs -= 1;
+
+            Some(slab_addr as *mut u8)
+        }
+    }
+
+    /// Take an empty slab, to be freed by the fallback allocator.
+    pub fn take_empty_slab(&mut self) -> Option<*mut u8> {
+        let slab_ptr = self.empty?;
+
+        #[expect(unsafe_code)]
+        // SAFETY: The slab is linked in the empty list.
+        unsafe {
+            Self::remove(&mut self.empty, slab_ptr);
+        }
+        self.empty_len -= 1;
+        self.slabs -= 1;
+
+        Some(slab_ptr.as_ptr().cast::<u8>())
+    }
+
+    /// Add a slab to the front of a list.
+    #[expect(unsafe_code)]
+    const unsafe fn push(head: &mut Option<NonNull<Slab>>, mut slab_ptr: NonNull<Slab>) {
+        let slab = slab_ptr.as_mut();
+
+        slab.prev = None;
+        slab.next = *head;
+
+        if let Some(mut next) = *head {
+            next.as_mut().prev = Some(slab_ptr);
+        }
+
+        *head = Some(slab_ptr);
+    }
+
+    /// Unlink a slab from the list it's part of.
+    #[expect(unsafe_code)]
+    const unsafe fn remove(head: &mut Option<NonNull<Slab>>, mut slab_ptr: NonNull<Slab>) {
+        let slab = slab_ptr.as_mut();
+
+        if let Some(mut prev) = slab.prev {
+            prev.as_mut().next = slab.next;
+        } else {
+            *head = slab.next;
+        }
+        if let Some(mut next) = slab.next {
+            next.as_mut().prev = slab.prev;
+        }
+
+        slab.prev = None;
+        slab.next = None;
+    }
+}
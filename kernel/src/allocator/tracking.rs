@@ -0,0 +1,165 @@
+use crate::dbg_println;
+
+/// Slots of the table, a power of 2.
+const SLOTS: usize = 4096;
+/// Maximum number of live allocations recorded at once, leaving free slots to keep
+/// probes short.
+#[expect(clippy::integer_division)]
+const MAX_TRACKED: usize = SLOTS / 4 * 3;
+/// Number of return addresses recorded for every allocation.
+const CALLERS_DEPTH: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Allocation {
+    ptr: usize,
+    size: usize,
+    /// Return addresses of the innermost callers, starting from the allocator's caller.
+    callers: [usize; CALLERS_DEPTH],
+}
+
+/// Records every live allocation in a fixed hash table, since it can't allocate itself.
+///
+/// Allocations are found by linear probing from the slot their address hashes to.
+pub struct Tracker {
+    allocations: [Option<Allocation>; SLOTS],
+    tracked: usize,
+    /// Allocations that didn't fit in the table.
+    untracked: usize,
+}
+
+impl Tracker {
+    #[expect(clippy::large_stack_arrays)]
+    pub const fn new() -> Self {
+        Self {
+            allocations: [None; SLOTS],
+            tracked: 0,
+            untracked: 0,
+        }
+    }
+
+    /// Record an allocation made by the allocator function whose frame is at
+    /// `allocator_frame`, see `frame_pointer`.
+    pub fn insert(&mut self, ptr: *mut u8, size: usize, allocator_frame: usize) {
+        let allocation = Allocation {
+            ptr: ptr as usize,
+            size,
+            callers: callers(allocator_frame),
+        };
+
+        if self.tracked == MAX_TRACKED {
+            self.untracked += 1;
+            return;
+        }
+
+        let mut slot = home_slot(allocation.ptr);
+        while self.allocations[slot].is_some() {
+            slot = (slot + 1) % SLOTS;
+        }
+        self.allocations[slot] = Some(allocation);
+        self.tracked += 1;
+    }
+
+    pub fn remove(&mut self, ptr: *mut u8) {
+        let mut slot = home_slot(ptr as usize);
+        loop {
+            match self.allocations[slot] {
+                Some(allocation) if allocation.ptr == ptr as usize => break,
+                Some(_) => slot = (slot + 1) % SLOTS,
+                None => {
+                    self.untracked = self.untracked.saturating_sub(1);
+                    return;
+                }
+            }
+        }
+
+        // shift back the following allocations that can't be found past the hole anymore
+        let mut hole = slot;
+        let mut next = (hole + 1) % SLOTS;
+        while let Some(allocation) = self.allocations[next] {
+            let distance = |from: usize| (next + SLOTS - from) % SLOTS;
+
+            if distance(home_slot(allocation.ptr)) >= distance(hole) {
+                self.allocations[hole] = Some(allocation);
+                hole = next;
+            }
+            next = (next + 1) % SLOTS;
+        }
+        self.allocations[hole] = None;
+        self.tracked -= 1;
+    }
+
+    pub fn dump(&self) {
+        dbg_println!("Live heap allocations:");
+
+        for allocation in self.allocations.iter().flatten() {
+            dbg_println!(
+                "  {:#x} ({} bytes) allocated by {:#x?}",
+                allocation.ptr,
+                allocation.size,
+                allocation.callers
+            );
+        }
+
+        if self.untracked > 0 {
+            dbg_println!("  ... and {} untracked allocations", self.untracked);
+        }
+    }
+}
+
+/// Slot of the table where the search for the allocation at `ptr` starts.
+const fn home_slot(ptr: usize) -> usize {
+    // Fibonacci hashing, keeping the top bits of the product
+    ptr.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (usize::BITS - SLOTS.trailing_zeros())
+}
+
+/// The frame pointer of the calling function.
+///
+/// `GlobalAlloc::alloc` passes its own to `Tracker::insert`, so the recorded callers start
+/// at the return address of the allocator whatever was inlined into it.
+// inlined, otherwise it would read its own frame pointer
+#[expect(clippy::inline_always)]
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let frame_pointer: usize;
+
+    #[expect(unsafe_code)]
+    // SAFETY: Only reads the frame pointer register.
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack));
+    }
+
+    frame_pointer
+}
+
+/// Walk the frame pointer chain from the allocator's frame at `frame_pointer`.
+///
+/// Requires the kernel to be built with frame pointers.
+fn callers(mut frame_pointer: usize) -> [usize; CALLERS_DEPTH] {
+    let mut callers = [0; CALLERS_DEPTH];
+
+    for caller in &mut callers {
+        if frame_pointer == 0 || !frame_pointer.is_multiple_of(align_of::<usize>()) {
+            break;
+        }
+
+        #[expect(clippy::multiple_unsafe_ops_per_block)]
+        #[expect(unsafe_code)]
+        // SAFETY: With frame pointers, each frame starts with the caller's frame pointer
+        // followed by the return address.
+        let (next_frame_pointer, return_address) = unsafe {
+            let frame = frame_pointer as *const usize;
+
+            (frame.read(), frame.add(1).read())
+        };
+
+        *caller = return_address;
+
+        // the stack grows down, so callers' frames must be above
+        if next_frame_pointer <= frame_pointer {
+            break;
+        }
+        frame_pointer = next_frame_pointer;
+    }
+
+    callers
+}
@@ -1,20 +1,37 @@
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::task::{Context, Poll, Waker};
-
-use crossbeam_queue::ArrayQueue;
+use alloc::{sync::Arc, task::Wake, vec::Vec};
+use core::{
+    alloc::AllocError,
+    task::{Context, Poll, Waker},
+};
 
 use super::{Task, TaskId};
+use crate::allocator::FixedQueue;
 
 const TASK_QUEUE_SIZE: usize = 100;
 
+/// Why a task couldn't be spawned. The task is handed back, so it can be retried later.
+pub enum SpawnError {
+    /// The task queue has no room left.
+    QueueFull(Task),
+    /// There is no memory left to keep track of the task.
+    OutOfMemory(Task),
+}
+
+struct Entry {
+    task: Task,
+    // Also, ensures that reference-counted wakers are not deallocated inside interrupt handlers
+    waker: Option<Waker>,
+}
+
 // TODO: Utilize CPU threads with load balancing.
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
+    // Sorted by task id, so tasks can be added without aborting when out of memory
+    tasks: Vec<Entry>,
     // Interrupt handlers should not allocate on push to this queue, so it's fixed size
     // TODO: Prioritize latency-critical tasks or tasks that do a lot of I/O (Scheduling).
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    // Also, ensures that reference-counted wakers are not deallocated inside interrupt handlers
-    waker_cache: BTreeMap<TaskId, Waker>,
+    task_queue: Arc<FixedQueue<TaskId>>,
+    // Woken task whose waker couldn't be allocated, polled first on the next run
+    retry: Option<TaskId>,
 }
 
 impl Executor {
@@ -22,60 +39,124 @@ impl Executor {
     #[must_use]
     pub fn new() -> Self {
         Self {
-            tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
-            waker_cache: BTreeMap::new(),
+            tasks: Vec::new(),
+            task_queue: Arc::new(FixedQueue::new(TASK_QUEUE_SIZE)),
+            retry: None,
         }
     }
 
+    /// Like `new`, but fails instead of aborting when the task queue can't be allocated.
+    ///
+    /// # Errors
+    ///
+    /// When the heap is out of memory.
+    pub fn try_new() -> Result<Self, AllocError> {
+        let task_queue = FixedQueue::try_new(TASK_QUEUE_SIZE)?;
+
+        Ok(Self {
+            tasks: Vec::new(),
+            task_queue: Arc::try_new(task_queue)?,
+            retry: None,
+        })
+    }
+
     // TODO: Create an additional Spawner type that shares some kind of queue with the executor
     // and allows task creation from within tasks themselves.
     // Since `spawn` no longer available after invoking the `run` method.
     #[expect(clippy::missing_panics_doc)]
     pub fn spawn(&mut self, task: Task) {
+        match self.try_spawn(task) {
+            Ok(()) => (),
+            Err(SpawnError::QueueFull(_)) => panic!("task_queue full"),
+            Err(SpawnError::OutOfMemory(_)) => panic!("out of memory for tasks"),
+        }
+    }
+
+    /// Like `spawn`, but hands the task back instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// When the task queue is full or the heap is out of memory.
+    ///
+    /// # Panics
+    ///
+    /// When a task with the same id was already spawned.
+    pub fn try_spawn(&mut self, task: Task) -> Result<(), SpawnError> {
         let task_id = task.id;
 
         // PERF: Do we really need to check for duplicated task ids?
-        assert!(
-            self.tasks.insert(task.id, task).is_none(),
-            "task with same ID already in tasks"
-        );
+        let Err(index) = self.find(task_id) else {
+            panic!("task with same ID already in tasks");
+        };
 
-        self.task_queue.push(task_id).expect("task_queue full");
+        if self.tasks.try_reserve(1).is_err() {
+            return Err(SpawnError::OutOfMemory(task));
+        }
+        self.tasks.insert(index, Entry { task, waker: None });
+
+        if self.task_queue.push(task_id).is_err() {
+            return Err(SpawnError::QueueFull(self.tasks.remove(index).task));
+        }
+
+        Ok(())
+    }
+
+    /// Index of the task with `task_id` in `tasks`, or where it would be inserted.
+    fn find(&self, task_id: TaskId) -> Result<usize, usize> {
+        self.tasks
+            .binary_search_by_key(&task_id, |entry| entry.task.id)
     }
 
-    fn run_ready_tasks(&mut self) {
-        while let Some(task_id) = self.task_queue.pop() {
+    /// Poll the tasks in the queue until it's empty.
+    ///
+    /// Stops early when the waker of a task can't be allocated, keeping the task to be
+    /// polled first on the next run.
+    fn run_ready_tasks(&mut self) -> Result<(), AllocError> {
+        while let Some(task_id) = self.retry.take().or_else(|| self.task_queue.pop()) {
             // Since a wake-up might occurs for a task that no longer exists
-            let Some(task) = self.tasks.get_mut(&task_id) else {
+            let Ok(index) = self.find(task_id) else {
                 continue; // task no longer exists
             };
-
-            let waker = self.waker_cache.entry(task_id).or_insert_with(|| {
-                Waker::from(Arc::new(TaskWaker::new(
-                    task_id,
-                    Arc::<ArrayQueue<TaskId>>::clone(&self.task_queue),
-                )))
-            });
+            let entry = &mut self.tasks[index];
+
+            let waker = if let Some(waker) = entry.waker.take() {
+                waker
+            } else {
+                let task_waker =
+                    TaskWaker::new(task_id, Arc::<FixedQueue<TaskId>>::clone(&self.task_queue));
+
+                match Arc::try_new(task_waker) {
+                    Ok(task_waker) => Waker::from(task_waker),
+                    Err(err) => {
+                        self.retry = Some(task_id);
+                        return Err(err);
+                    }
+                }
+            };
+            let waker = entry.waker.insert(waker);
 
             let mut context = Context::from_waker(waker);
 
-            match task.poll(&mut context) {
+            match entry.task.poll(&mut context) {
                 Poll::Ready(()) => {
                     // task done -> remove it and its cached waker
-                    self.tasks.remove(&task_id);
-                    self.waker_cache.remove(&task_id);
+                    self.tasks.remove(index);
                 }
                 Poll::Pending => {}
             }
         }
+
+        Ok(())
     }
 
     pub fn run(&mut self) -> ! {
         loop {
-            self.run_ready_tasks();
-
-            self.sleep_if_idle();
+            if self.run_ready_tasks().is_err() {
+                // Retry after the next interrupt instead of spinning until memory is freed
+                x86_64::instructions::interrupts::enable_and_hlt();
+            } else {
+                self.sleep_if_idle();
+            }
         }
     }
 
@@ -94,11 +175,11 @@ impl Executor {
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    task_queue: Arc<FixedQueue<TaskId>>,
 }
 
 impl TaskWaker {
-    const fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Self {
+    const fn new(task_id: TaskId, task_queue: Arc<FixedQueue<TaskId>>) -> Self {
         Self {
             task_id,
             task_queue,
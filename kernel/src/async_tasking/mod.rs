@@ -2,13 +2,14 @@ mod executor;
 
 use alloc::boxed::Box;
 use core::{
+    alloc::AllocError,
     future::Future,
     pin::Pin,
     sync::atomic::{AtomicU64, Ordering},
     task::{Context, Poll},
 };
 
-pub use executor::Executor;
+pub use executor::{Executor, SpawnError};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
@@ -34,6 +35,20 @@ impl Task {
         }
     }
 
+    /// Like `new`, but fails instead of aborting when the future can't be allocated.
+    ///
+    /// # Errors
+    ///
+    /// When the heap is out of memory.
+    pub fn try_new(future: impl Future<Output = ()> + 'static) -> Result<Self, AllocError> {
+        let future: Box<dyn Future<Output = ()>> = Box::try_new(future)?;
+
+        Ok(Self {
+            id: TaskId::new(),
+            future: Box::into_pin(future),
+        })
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
@@ -0,0 +1,248 @@
+use core::fmt;
+
+use spin::once::Once;
+use x86_64::{structures::idt::InterruptStackFrame, VirtAddr};
+
+use crate::{dbg_println, exception};
+
+/// Frames printed at most, in case the frame pointer chain loops.
+const MAX_FRAMES: usize = 64;
+
+const MAGIC: &[u8] = b"KSYM";
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 24;
+
+/// The function symbols of the kernel, extracted from its ELF by the build script and
+/// loaded as the ramdisk.
+static SYMBOLS: Once<SymbolTable> = Once::new();
+
+struct SymbolTable {
+    /// Entries sorted by address, see `write_symbol_table` in `build.rs`.
+    entries: &'static [u8],
+    count: usize,
+    names: &'static [u8],
+    /// Where the kernel was loaded, symbol addresses are relative to it.
+    image_offset: u64,
+}
+
+impl SymbolTable {
+    fn parse(table: &'static [u8], image_offset: u64) -> Option<Self> {
+        let (header, rest) = table.split_at_checked(HEADER_SIZE)?;
+        let (magic, count) = header.split_at(MAGIC.len());
+
+        if magic != MAGIC {
+            return None;
+        }
+
+        let count = usize::try_from(u32::from_le_bytes(count.try_into().ok()?)).ok()?;
+        let (entries, names) = rest.split_at_checked(count.checked_mul(ENTRY_SIZE)?)?;
+
+        Some(Self {
+            entries,
+            count,
+            names,
+            image_offset,
+        })
+    }
+
+    /// Name of the function containing `addr`, and the offset of `addr` in it.
+    fn lookup(&self, addr: VirtAddr) -> Option<(&'static str, u64)> {
+        let addr = addr.as_u64().checked_sub(self.image_offset)?;
+
+        // binary search for the last function starting at or before `addr`
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let middle = low + ((high - low) >> 1_u32);
+
+            if self.field(middle, 0) <= addr {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        let index = low.checked_sub(1)?;
+
+        let offset = addr - self.field(index, 0);
+        if offset >= self.field(index, 8) {
+            return None;
+        }
+
+        let name = self.field(index, 16);
+        let name_start = usize::try_from(name & u64::from(u32::MAX)).ok()?;
+        let name_end = name_start.checked_add(usize::try_from(name >> 32_u32).ok()?)?;
+
+        let name = core::str::from_utf8(self.names.get(name_start..name_end)?).ok()?;
+
+        Some((name, offset))
+    }
+
+    /// The `u64` at `offset` in the entry at `index`.
+    fn field(&self, index: usize, offset: usize) -> u64 {
+        let start = index * ENTRY_SIZE + offset;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.entries[start..start + 8]);
+
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// Load the kernel symbols from the `table` built by `build.rs`, for the kernel image
+/// loaded at `image_offset`.
+///
+/// Backtraces show bare addresses when the table is missing or malformed.
+pub(crate) fn init(table: Option<&'static [u8]>, image_offset: u64) {
+    match table.and_then(|table| SymbolTable::parse(table, image_offset)) {
+        Some(symbols) => {
+            SYMBOLS.call_once(|| symbols);
+        }
+        None => dbg_println!("No kernel symbol table, backtraces won't be symbolized"),
+    }
+}
+
+/// A code address, displayed with the function containing it when it's known.
+struct Symbolized {
+    address: VirtAddr,
+    /// Return addresses are looked up one byte before, since a call can end a function.
+    is_return_address: bool,
+}
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let call_site = u64::from(self.is_return_address);
+        let symbol = SYMBOLS
+            .get()
+            .and_then(|symbols| symbols.lookup(self.address - call_site));
+
+        match symbol {
+            Some((name, offset)) => write!(
+                f,
+                "{:#x} - {name}+{:#x}",
+                self.address.as_u64(),
+                offset + call_site
+            ),
+            None => write!(f, "{:#x} - <unknown>", self.address.as_u64()),
+        }
+    }
+}
+
+/// Print the call chain of the caller to serial.
+///
+/// Requires the kernel to be built with frame pointers.
+#[inline(never)]
+pub fn print() {
+    dbg_println!("Backtrace:");
+
+    for (index, return_address) in Frames::new(frame_pointer()).enumerate() {
+        print_frame(index, return_address, true);
+    }
+}
+
+/// Print the call chain of the code interrupted by an exception with `stack_frame` to
+/// serial, starting at the faulting instruction.
+///
+/// Must be called from the exception handler, or from functions it calls.
+#[inline(never)]
+pub(crate) fn print_exception(stack_frame: &InterruptStackFrame) {
+    dbg_println!("Backtrace of the interrupted code:");
+    print_frame(0, stack_frame.instruction_pointer, false);
+
+    // The handler pushes the interrupted frame pointer right below the stack frame pushed
+    // by the CPU, and its error code if there is one.
+    let stack_frame_addr = VirtAddr::from_ptr(stack_frame).as_u64();
+    let handler_frame = Frames::new(frame_pointer())
+        .links()
+        .find(|&(frame_pointer, _)| {
+            frame_pointer < stack_frame_addr && stack_frame_addr - frame_pointer <= 16
+        });
+
+    let Some((_, interrupted_frame_pointer)) = handler_frame else {
+        dbg_println!("  <the exception handler frame wasn't found>");
+        return;
+    };
+
+    for (index, return_address) in Frames::new(interrupted_frame_pointer).enumerate() {
+        print_frame(index + 1, return_address, true);
+    }
+}
+
+fn print_frame(index: usize, address: VirtAddr, is_return_address: bool) {
+    let address = Symbolized {
+        address,
+        is_return_address,
+    };
+
+    dbg_println!("  {:>2}: {}", index, address);
+}
+
+fn frame_pointer() -> u64 {
+    let frame_pointer: u64;
+
+    #[expect(unsafe_code)]
+    // SAFETY: Only reads the frame pointer register.
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack));
+    }
+
+    frame_pointer
+}
+
+/// Return addresses of the frame pointer chain, from the innermost frame.
+///
+/// Frames are read with fallible reads, so a corrupted chain ends the walk instead of
+/// faulting again.
+struct Frames {
+    frame_pointer: u64,
+    walked: usize,
+}
+
+impl Frames {
+    const fn new(frame_pointer: u64) -> Self {
+        Self {
+            frame_pointer,
+            walked: 0,
+        }
+    }
+
+    /// The frame pointers of the chain, each with the frame pointer saved in its frame.
+    fn links(mut self) -> impl Iterator<Item = (u64, u64)> {
+        core::iter::from_fn(move || {
+            let frame_pointer = self.frame_pointer;
+            self.next()?;
+
+            Some((frame_pointer, self.frame_pointer))
+        })
+    }
+}
+
+impl Iterator for Frames {
+    type Item = VirtAddr;
+
+    fn next(&mut self) -> Option<VirtAddr> {
+        if self.walked == MAX_FRAMES
+            || self.frame_pointer == 0
+            || !self.frame_pointer.is_multiple_of(8)
+        {
+            return None;
+        }
+        let frame = VirtAddr::try_new(self.frame_pointer).ok()?;
+
+        // With frame pointers, each frame starts with the caller's frame pointer followed by
+        // the return address.
+        #[expect(unsafe_code)]
+        // SAFETY: Stack memory has no side effects when read.
+        let next_frame_pointer = unsafe { exception::read_word(frame) }?;
+        #[expect(unsafe_code)]
+        // SAFETY: Same as above.
+        let return_address = unsafe { exception::read_word(frame + 8) }?;
+
+        // the outermost frame has no return address
+        let return_address = VirtAddr::try_new(return_address)
+            .ok()
+            .filter(|addr| !addr.is_null())?;
+
+        self.frame_pointer = next_frame_pointer;
+        self.walked += 1;
+
+        Some(return_address)
+    }
+}
@@ -0,0 +1,35 @@
+/// Errors reported by a `BlockDevice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The blocks are past the end of the device.
+    OutOfRange,
+    /// The device failed to transfer the blocks.
+    Io,
+}
+
+/// A storage device read and written in fixed-size blocks, like a disk or one of its
+/// partitions.
+///
+/// Devices used for swap must neither allocate nor fault, see `enable_swap`.
+pub trait BlockDevice: Send {
+    /// Size of a block in bytes, a power of 2.
+    fn block_size(&self) -> usize;
+
+    fn block_count(&self) -> u64;
+
+    /// Read the blocks starting at `start` into `buffer`, whose length is a multiple of
+    /// the block size.
+    ///
+    /// # Errors
+    ///
+    /// When the blocks are out of range or can't be read.
+    fn read_blocks(&mut self, start: u64, buffer: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Write `buffer`, whose length is a multiple of the block size, to the blocks
+    /// starting at `start`.
+    ///
+    /// # Errors
+    ///
+    /// When the blocks are out of range or can't be written.
+    fn write_blocks(&mut self, start: u64, buffer: &[u8]) -> Result<(), BlockError>;
+}
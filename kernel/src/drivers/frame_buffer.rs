@@ -9,6 +9,9 @@ use embedded_graphics::{
     Pixel,
 };
 use spin::{once::Once, Mutex};
+use x86_64::VirtAddr;
+
+use crate::{dbg_println, memory};
 
 pub static DISPLAY: Once<Mutex<Display>> = Once::new();
 
@@ -18,6 +21,8 @@ pub struct Display {
 
 impl Display {
     pub fn new(framebuffer: &'static mut FrameBuffer) -> Self {
+        use_write_combining(framebuffer);
+
         Self { framebuffer }
     }
 
@@ -107,6 +112,30 @@ impl Display {
     }
 }
 
+/// Switch the framebuffer to write-combining, which is a lot faster to fill than the
+/// memory type picked by the bootloader.
+///
+/// Keeps the bootloader's memory type when the mappings can't be updated.
+fn use_write_combining(framebuffer: &FrameBuffer) {
+    let start = VirtAddr::from_ptr(framebuffer.buffer().as_ptr());
+
+    #[expect(unsafe_code)]
+    // SAFETY: The framebuffer is only mapped by the bootloader and the physical memory
+    // mapping, which are both updated.
+    if let Err(err) = unsafe {
+        memory::set_cache_policy(
+            start,
+            framebuffer.info().byte_len,
+            memory::CachePolicy::WriteCombining,
+        )
+    } {
+        dbg_println!(
+            "Failed to map the framebuffer as write-combining: {:?}",
+            err
+        );
+    }
+}
+
 impl OriginDimensions for Display {
     fn size(&self) -> Size {
         let info = self.framebuffer.info();
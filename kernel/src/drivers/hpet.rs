@@ -0,0 +1,399 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::once::Once;
+use x86_64::{instructions::interrupts::without_interrupts, PhysAddr, VirtAddr};
+
+use crate::{
+    dbg_println,
+    interrupts::apic::io,
+    irq::{register_irq, unregister_irq, Irq, IrqError, IrqHandler, IrqPriority, IrqSource},
+    memory,
+};
+
+/// Size of the memory mapped registers of an HPET.
+const REGISTERS_SIZE: usize = 0x400;
+
+// Registers
+const CAPABILITIES: usize = 0x000;
+const CONFIGURATION: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0f0;
+
+const fn comparator_configuration(index: u8) -> usize {
+    0x100 + 0x20 * index as usize
+}
+
+const fn comparator_value(index: u8) -> usize {
+    0x108 + 0x20 * index as usize
+}
+
+const fn comparator_fsb_route(index: u8) -> usize {
+    0x110 + 0x20 * index as usize
+}
+
+// General capabilities
+const COUNTER_64_BIT: u64 = 1 << 13;
+
+// General configuration
+const ENABLE: u64 = 1 << 0;
+const LEGACY_REPLACEMENT: u64 = 1 << 1;
+
+// Comparator configuration and capabilities
+const LEVEL_TRIGGERED: u64 = 1 << 1;
+const INTERRUPT_ENABLE: u64 = 1 << 2;
+const PERIODIC: u64 = 1 << 3;
+const PERIODIC_CAPABLE: u64 = 1 << 4;
+const VALUE_SET: u64 = 1 << 6;
+const ROUTE_SHIFT: u32 = 9;
+const ROUTE: u64 = 0x1f << ROUTE_SHIFT;
+const FSB_ENABLE: u64 = 1 << 14;
+const FSB_CAPABLE: u64 = 1 << 15;
+const ROUTES_SHIFT: u32 = 32;
+
+const FEMTOSECONDS_PER_NANOSECOND: u64 = 1_000_000;
+
+/// Fewest ticks a comparator is programmed ahead of the main counter, since writing it
+/// takes time: Linux's `HPET_MIN_CYCLES`.
+const MIN_TICKS: u64 = 128;
+
+/// Longest period of the main counter allowed by the specification, in femtoseconds
+/// (100 ns).
+const MAX_PERIOD: u64 = 0x05f5_e100;
+
+static HPET: Once<Hpet> = Once::new();
+
+#[derive(Debug)]
+pub enum HpetError {
+    /// The HPET has no comparator with this index.
+    NoSuchComparator,
+    /// The comparator can't fire periodically.
+    NotPeriodic,
+    /// The comparator can't be routed to any interrupt line.
+    NoRoute,
+    /// The main counter passed the deadline before the comparator was armed.
+    DeadlinePassed,
+    Irq(IrqError),
+}
+
+impl From<IrqError> for HpetError {
+    fn from(err: IrqError) -> Self {
+        Self::Irq(err)
+    }
+}
+
+/// The High Precision Event Timer: a monotonic counter, with comparators raising
+/// interrupts when it reaches their value.
+#[derive(Debug)]
+pub struct Hpet {
+    registers: VirtAddr,
+    /// Period of the main counter, in femtoseconds.
+    period: u64,
+    comparators: u8,
+    counter_64_bit: bool,
+    /// Last value read from a 32-bit main counter, extended to 64 bits.
+    last_counter: AtomicU64,
+}
+
+/// Map the registers of the HPET at `base_address`, and start its main counter.
+///
+/// The HPET is left unused when it reports an invalid counter period.
+///
+/// # Panics
+///
+/// When its registers can't be mapped.
+pub(crate) fn init(base_address: PhysAddr) {
+    let mapping = memory::map_mmio(base_address, REGISTERS_SIZE, memory::CachePolicy::Uncached)
+        .expect("Failed to map HPET registers");
+
+    let mut hpet = Hpet {
+        registers: mapping.virt_addr(),
+        period: 0,
+        comparators: 0,
+        counter_64_bit: false,
+        last_counter: AtomicU64::new(0),
+    };
+
+    let capabilities = hpet.read(CAPABILITIES);
+    hpet.period = capabilities >> 32_u32;
+    if !(1..=MAX_PERIOD).contains(&hpet.period) {
+        dbg_println!(
+            "HPET: invalid counter period of {} fs, not used",
+            hpet.period
+        );
+        return;
+    }
+
+    hpet.comparators = ((capabilities >> 8_u32) & 0x1f) as u8 + 1;
+    hpet.counter_64_bit = capabilities & COUNTER_64_BIT != 0;
+
+    // comparators only raise the interrupts they are registered for
+    for index in 0..hpet.comparators {
+        let configuration = hpet.read(comparator_configuration(index));
+        hpet.write(
+            comparator_configuration(index),
+            configuration & !(INTERRUPT_ENABLE | PERIODIC),
+        );
+    }
+
+    let configuration = hpet.read(CONFIGURATION);
+    hpet.write(
+        CONFIGURATION,
+        (configuration & !LEGACY_REPLACEMENT) | ENABLE,
+    );
+
+    dbg_println!(
+        "HPET: {} Hz, {} comparators, {}-bit counter",
+        hpet.frequency(),
+        hpet.comparators,
+        if hpet.counter_64_bit { 64_u8 } else { 32_u8 }
+    );
+
+    // The HPET is used for the rest of the kernel's lifetime
+    hpet.registers = mapping.leak();
+    HPET.call_once(|| hpet);
+}
+
+/// The HPET, when the ACPI tables describe one.
+#[must_use]
+pub fn get() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+impl Hpet {
+    /// Frequency of the main counter.
+    #[must_use]
+    #[expect(clippy::integer_division)]
+    pub const fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period
+    }
+
+    /// Value of the main counter, which never goes backwards.
+    ///
+    /// A 32-bit counter is extended to 64 bits, so it must be read at least once per
+    /// wraparound: every 5 minutes at the usual 14.3 MHz.
+    #[must_use]
+    pub fn counter(&self) -> u64 {
+        if self.counter_64_bit {
+            return self.read(MAIN_COUNTER);
+        }
+
+        without_interrupts(|| {
+            let last = self.last_counter.load(Ordering::Relaxed);
+            let mut counter =
+                (last & !u64::from(u32::MAX)) | (self.read(MAIN_COUNTER) & u64::from(u32::MAX));
+
+            if counter < last {
+                counter += 1 << 32_u32;
+            }
+            self.last_counter.store(counter, Ordering::Relaxed);
+
+            counter
+        })
+    }
+
+    /// Nanoseconds since the main counter was started.
+    #[must_use]
+    pub fn nanos(&self) -> u64 {
+        #[expect(clippy::integer_division)]
+        #[expect(clippy::cast_possible_truncation)]
+        {
+            (u128::from(self.counter()) * u128::from(self.period)
+                / u128::from(FEMTOSECONDS_PER_NANOSECOND)) as u64
+        }
+    }
+
+    /// Busy wait for `ns` nanoseconds.
+    pub fn wait_ns(&self, ns: u64) {
+        let end = self.nanos().saturating_add(ns);
+
+        while self.nanos() < end {
+            core::hint::spin_loop();
+        }
+    }
+
+    #[must_use]
+    pub const fn comparators(&self) -> u8 {
+        self.comparators
+    }
+
+    /// Call `handler` on the interrupts raised by the comparator at `index`, which is
+    /// left stopped.
+    ///
+    /// The interrupt is message signalled when the comparator supports it, and otherwise
+    /// routed through the first I/O APIC, on a line no ISA IRQ is wired to.
+    ///
+    /// # Errors
+    ///
+    /// When there is no such comparator, or its interrupt can't be registered.
+    pub fn register_comparator(
+        &'static self,
+        index: u8,
+        handler: impl IrqHandler + 'static,
+        priority: IrqPriority,
+    ) -> Result<Comparator, HpetError> {
+        if index >= self.comparators {
+            return Err(HpetError::NoSuchComparator);
+        }
+
+        let mut configuration = self.read(comparator_configuration(index))
+            & !(LEVEL_TRIGGERED | INTERRUPT_ENABLE | PERIODIC);
+
+        let irq = if configuration & FSB_CAPABLE == 0 {
+            // the routes are the pins of the first I/O APIC the comparator is wired to
+            let routes = configuration >> ROUTES_SHIFT;
+            let (pin, gsi) = (0..32_u8)
+                .filter(|&pin| routes & (1 << pin) != 0)
+                .find_map(|pin| Some((pin, io::first_io_apic_gsi(pin)?)))
+                .ok_or(HpetError::NoRoute)?;
+
+            let irq = register_irq(IrqSource::EdgeGsi(gsi), handler, priority)?;
+            configuration =
+                (configuration & !(ROUTE | FSB_ENABLE)) | (u64::from(pin) << ROUTE_SHIFT);
+
+            irq
+        } else {
+            let irq = register_irq(IrqSource::Msi, handler, priority)?;
+            let Some(message) = irq.msi_message() else {
+                unregister_irq(irq);
+                return Err(HpetError::NoRoute);
+            };
+
+            // the route holds the message address in its high half, and the data in its low one
+            self.write(
+                comparator_fsb_route(index),
+                (message.address << 32_u32) | u64::from(message.data),
+            );
+            configuration |= FSB_ENABLE;
+
+            irq
+        };
+
+        self.write(comparator_configuration(index), configuration);
+
+        Ok(Comparator {
+            hpet: self,
+            index,
+            irq,
+        })
+    }
+
+    /// Counter ticks in `ns` nanoseconds, at least `MIN_TICKS`.
+    fn ticks(&self, ns: u64) -> u64 {
+        #[expect(clippy::integer_division)]
+        #[expect(clippy::cast_possible_truncation)]
+        let ticks = (u128::from(ns) * u128::from(FEMTOSECONDS_PER_NANOSECOND)
+            / u128::from(self.period))
+        .min(u128::from(u64::MAX)) as u64;
+
+        ticks.max(MIN_TICKS)
+    }
+
+    fn read(&self, register: usize) -> u64 {
+        #[expect(unsafe_code)]
+        // SAFETY: The registers are mapped, and the offset is one of them.
+        unsafe {
+            core::ptr::read_volatile((self.registers + register as u64).as_ptr::<u64>())
+        }
+    }
+
+    fn write(&self, register: usize, value: u64) {
+        #[expect(unsafe_code)]
+        // SAFETY: The registers are mapped, and the offset is one of them.
+        unsafe {
+            core::ptr::write_volatile(
+                (self.registers + register as u64).as_mut_ptr::<u64>(),
+                value,
+            );
+        }
+    }
+}
+
+/// An HPET comparator with a registered interrupt handler, see
+/// `Hpet::register_comparator`.
+#[derive(Debug)]
+pub struct Comparator {
+    hpet: &'static Hpet,
+    index: u8,
+    irq: Irq,
+}
+
+impl Comparator {
+    #[must_use]
+    pub const fn irq(&self) -> Irq {
+        self.irq
+    }
+
+    /// Raise the interrupt once, in `ns` nanoseconds.
+    ///
+    /// # Errors
+    ///
+    /// When the main counter passed the deadline before the comparator was armed, so the
+    /// interrupt would only be raised after the counter wraps around. The comparator is
+    /// stopped, though its interrupt may have been raised already.
+    pub fn start_one_shot(&self, ns: u64) -> Result<(), HpetError> {
+        let configuration = self.configuration() & !PERIODIC;
+        self.set_configuration(configuration & !INTERRUPT_ENABLE);
+
+        let ticks = self.hpet.ticks(ns);
+        let start = self.hpet.counter();
+        self.hpet
+            .write(comparator_value(self.index), start.wrapping_add(ticks));
+        self.set_configuration(configuration | INTERRUPT_ENABLE);
+
+        if self.hpet.counter().wrapping_sub(start) >= ticks {
+            self.stop();
+            return Err(HpetError::DeadlinePassed);
+        }
+
+        Ok(())
+    }
+
+    /// Raise the interrupt every `ns` nanoseconds.
+    ///
+    /// # Errors
+    ///
+    /// When the comparator can't fire periodically, or the main counter passed the first
+    /// deadline before the comparator was armed, as for `start_one_shot`.
+    pub fn start_periodic(&self, ns: u64) -> Result<(), HpetError> {
+        let configuration = self.configuration();
+        if configuration & PERIODIC_CAPABLE == 0 {
+            return Err(HpetError::NotPeriodic);
+        }
+        self.set_configuration(configuration & !INTERRUPT_ENABLE);
+
+        // with `VALUE_SET`, the first write sets the comparator and the second its period
+        let period = self.hpet.ticks(ns);
+        self.set_configuration(configuration | PERIODIC | VALUE_SET | INTERRUPT_ENABLE);
+        let start = self.hpet.counter();
+        self.hpet
+            .write(comparator_value(self.index), start.wrapping_add(period));
+        self.hpet.write(comparator_value(self.index), period);
+
+        if self.hpet.counter().wrapping_sub(start) >= period {
+            self.stop();
+            return Err(HpetError::DeadlinePassed);
+        }
+
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        let configuration = self.configuration();
+        self.set_configuration(configuration & !(INTERRUPT_ENABLE | PERIODIC));
+    }
+
+    fn configuration(&self) -> u64 {
+        self.hpet.read(comparator_configuration(self.index))
+    }
+
+    fn set_configuration(&self, configuration: u64) {
+        self.hpet
+            .write(comparator_configuration(self.index), configuration);
+    }
+}
+
+impl Drop for Comparator {
+    fn drop(&mut self) {
+        self.stop();
+        unregister_irq(self.irq);
+    }
+}
@@ -1,2 +1,5 @@
+pub mod block;
 pub mod frame_buffer;
+pub mod hpet;
+pub mod pit;
 pub mod serial;
@@ -0,0 +1,47 @@
+use x86_64::instructions::port::Port;
+
+/// Frequency of the PIT's input clock.
+pub const FREQUENCY: u32 = 1_193_182;
+
+/// Busy wait for `ms` milliseconds (at most 54), with channel 2 of the PIT.
+///
+/// Used to calibrate the other timers, so interrupts are neither needed nor raised.
+///
+/// # Panics
+///
+/// When `ms` is more than 54, which doesn't fit in the PIT's counter.
+pub fn wait_ms(ms: u32) {
+    #[expect(clippy::integer_division)]
+    let count = u16::try_from(u64::from(FREQUENCY) * u64::from(ms) / 1000)
+        .expect("PIT waits are at most 54 ms");
+
+    let mut control = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel_2 = Port::<u8>::new(0x42);
+
+    let [low, high] = count.to_le_bytes();
+
+    #[expect(clippy::multiple_unsafe_ops_per_block)]
+    #[expect(unsafe_code)]
+    // SAFETY: Channel 2 only drives the PC speaker, which stays disconnected.
+    unsafe {
+        // gate channel 2 off, and disconnect it from the speaker
+        let gate = control.read() & !0b11;
+        control.write(gate);
+
+        // channel 2, low then high byte, interrupt on terminal count
+        command.write(0b1011_0000);
+        channel_2.write(low);
+        channel_2.write(high);
+
+        // counting starts with the gate
+        control.write(gate | 1);
+
+        // the channel's output goes high at the end of the count
+        while control.read() & 0b10_0000 == 0 {
+            core::hint::spin_loop();
+        }
+
+        control.write(gate);
+    }
+}
@@ -14,16 +14,16 @@ use x86_64::{
 
 const STACK_SIZE: usize = memory::PAGE_SIZE * 5;
 
+/// Exceptions handled on their own stack.
+///
+/// Only double faults are: an IST stack restarts from its top on every exception, so an
+/// exception raised while its handler runs, e.g. by an exception table fixup, would
+/// overwrite the handler's frames. Double faults don't nest, and still have a stack
+/// when the faulting one overflowed.
 #[derive(Debug, Clone, Copy)]
 #[repr(u16)]
 pub enum IstIndex {
     DoubleFault = 0,
-    PageFault = 1,
-    InvalidTSS = 2,
-    DivideError = 3,
-    SegmentNotPresent = 4,
-    StackSegmentFault = 5,
-    GeneralProtectionFault = 6,
 }
 
 impl IstIndex {
@@ -34,6 +34,13 @@ impl IstIndex {
     const fn as_usize(self) -> usize {
         self as usize
     }
+
+    /// Name of the stack, used to report its overflows.
+    const fn stack_name(self) -> &'static str {
+        match self {
+            Self::DoubleFault => "double fault IST",
+        }
+    }
 }
 
 struct GdtWithSelectors {
@@ -43,37 +50,31 @@ struct GdtWithSelectors {
     tss_selector: SegmentSelector,
 }
 
-static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
-    let mut tss = TaskStateSegment::new();
-
-    macro_rules! ist_entry {
-        ($index:expr) => {
-            tss.interrupt_stack_table[$index] = {
-                static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+const IST_INDEXES: [IstIndex; 1] = [IstIndex::DoubleFault];
 
-                VirtAddr::from_ptr(&raw const STACK) + STACK_SIZE as u64 // stack end address
-            };
-        };
-    }
+/// Interrupt stacks used until memory is initialized, see `use_guarded_stacks`.
+#[repr(align(16))]
+struct EarlyStacks {
+    _stacks: [[u8; STACK_SIZE]; IST_INDEXES.len()],
+}
 
-    // Create a stack for those and add them to the table.
-    ist_entry!(IstIndex::DoubleFault.as_usize());
-    ist_entry!(IstIndex::PageFault.as_usize());
-    ist_entry!(IstIndex::InvalidTSS.as_usize());
-    ist_entry!(IstIndex::DivideError.as_usize());
-    ist_entry!(IstIndex::SegmentNotPresent.as_usize());
-    ist_entry!(IstIndex::StackSegmentFault.as_usize());
-    ist_entry!(IstIndex::GeneralProtectionFault.as_usize());
+static mut EARLY_STACKS: EarlyStacks = EarlyStacks {
+    _stacks: [[0; STACK_SIZE]; IST_INDEXES.len()],
+};
 
-    tss
-});
+/// The CPU reads the interrupt stack table on every interrupt, so it's updated in place
+/// when the interrupt stacks are replaced.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 static GDT: Lazy<GdtWithSelectors> = Lazy::new(|| {
     let mut gdt = GlobalDescriptorTable::new();
 
     let code_selector = gdt.append(Descriptor::kernel_code_segment());
     let data_selector = gdt.append(Descriptor::kernel_data_segment());
-    let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+    let tss = &raw const TSS;
+    #[expect(unsafe_code)]
+    // SAFETY: The TSS is a static.
+    let tss_selector = gdt.append(unsafe { Descriptor::tss_segment_unchecked(tss) });
 
     GdtWithSelectors {
         gdt,
@@ -83,7 +84,18 @@ static GDT: Lazy<GdtWithSelectors> = Lazy::new(|| {
     }
 });
 
+/// Load the GDT and the TSS, with interrupt stacks that don't need memory to be
+/// initialized.
 pub fn init() {
+    let early_stacks = VirtAddr::from_ptr(&raw const EARLY_STACKS);
+    for index in IST_INDEXES {
+        // stack end address
+        set_interrupt_stack(
+            index,
+            early_stacks + ((index.as_usize() + 1) * STACK_SIZE) as u64,
+        );
+    }
+
     GDT.gdt.load();
 
     // SAFETY: Reload the code segment register.
@@ -110,3 +122,31 @@ pub fn init() {
         load_tss(GDT.tss_selector);
     }
 }
+
+/// Replace the early interrupt stacks with stacks that have a guard page, so their
+/// overflows are reported.
+///
+/// Must be called once memory is initialized, with interrupts disabled.
+pub fn use_guarded_stacks() {
+    for index in IST_INDEXES {
+        let stack = memory::Stack::new(STACK_SIZE, index.stack_name())
+            .expect("Failed to allocate an interrupt stack")
+            .leak(); // stack end address
+
+        set_interrupt_stack(index, stack);
+    }
+}
+
+/// Point the interrupt stack table entry `index` to the stack ending at `top`.
+///
+/// Interrupts must be disabled, so no handler is running on the previous stack.
+fn set_interrupt_stack(index: IstIndex, top: VirtAddr) {
+    let tss = &raw mut TSS;
+
+    #[expect(unsafe_code)]
+    // SAFETY: The TSS is only written with interrupts disabled, and the CPU only reads it
+    // when an interrupt occurs.
+    unsafe {
+        (*tss).interrupt_stack_table[index.as_usize()] = top;
+    }
+}
@@ -1,55 +1,203 @@
 use alloc::{alloc::Global, vec::Vec};
 
-use acpi::platform::interrupt::Apic;
+use acpi::platform::interrupt::{Apic, Polarity, TriggerMode};
+use spin::{once::Once, Mutex};
 use x2apic::ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry};
 
-use super::local::LAPIC;
-use crate::{interrupts::InterruptIndex, memory};
+use super::local;
+use crate::{
+    interrupts::{irq::IrqError, InterruptIndex},
+    memory,
+};
+
+/// Size of the memory mapped registers of an I/O APIC (index and data).
+const IO_APIC_REGISTERS_SIZE: usize = 0x20;
+
+/// Number of ISA IRQs, from the legacy PICs.
+const ISA_IRQS: usize = 16;
+
+static IO_APICS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// The line of each ISA IRQ, after the interrupt source overrides of the MADT.
+static ISA_LINES: Once<[Line; ISA_IRQS]> = Once::new();
+
+/// An interrupt line, as wired to an I/O APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(in crate::interrupts) struct Line {
+    pub gsi: u32,
+    pub level_triggered: bool,
+    pub active_low: bool,
+}
+
+impl Line {
+    /// A line following the PCI convention: level triggered and active low.
+    pub const fn pci(gsi: u32) -> Self {
+        Self {
+            gsi,
+            level_triggered: true,
+            active_low: true,
+        }
+    }
+
+    /// An edge triggered and active high line.
+    pub const fn edge(gsi: u32) -> Self {
+        Self {
+            gsi,
+            level_triggered: false,
+            active_low: false,
+        }
+    }
+
+    /// An ISA line with no override: identity mapped, edge triggered and active high.
+    const fn isa(irq: u8) -> Self {
+        Self::edge(irq as u32)
+    }
+}
+
+struct Entry {
+    ioapic: IoApic,
+    /// The first GSI handled by the I/O APIC, on its first pin.
+    gsi_base: u32,
+    pins: u8,
+}
 
 pub fn init(apic: &Apic<Global>) {
-    let mut ioapic_vec: Vec<IoApic> = Vec::new();
+    let mut io_apics = IO_APICS.lock();
 
     for ioapic in apic.io_apics.iter() {
+        // The I/O APICs are used for the rest of the kernel's lifetime
+        let registers = memory::map_mmio(
+            x86_64::PhysAddr::new(u64::from(ioapic.address)),
+            IO_APIC_REGISTERS_SIZE,
+            memory::CachePolicy::Uncached,
+        )
+        .expect("Failed to map I/O APIC registers")
+        .leak();
+
         #[expect(unsafe_code)]
         // SAFETY: Address is mapped to the I/O APIC physical address.
-        ioapic_vec.push(unsafe {
-            IoApic::new(
-                memory::physical_to_virtual(x86_64::PhysAddr::new(u64::from(ioapic.address)))
-                    .as_u64(),
-            )
-        });
-    }
+        let mut registers = unsafe { IoApic::new(registers.as_u64()) };
 
-    for mut ioapic in ioapic_vec {
         #[expect(unsafe_code)]
         // SAFETY: Offset is correct.
         unsafe {
-            ioapic.init(InterruptIndex::offset());
+            registers.init(InterruptIndex::offset());
         }
 
-        // TODO: Should we really assign the keyboard IRQ to every I/O APIC?
-        assign_irq_entry(InterruptIndex::Keyboard, ioapic);
+        #[expect(unsafe_code)]
+        // SAFETY: The registers are mapped.
+        let pins = unsafe { registers.max_table_entry() } + 1;
+
+        io_apics.push(Entry {
+            ioapic: registers,
+            gsi_base: ioapic.global_system_interrupt_base,
+            pins,
+        });
+    }
+
+    let mut isa_lines: [Line; ISA_IRQS] = core::array::from_fn(|irq| {
+        #[expect(clippy::cast_possible_truncation)]
+        Line::isa(irq as u8)
+    });
+
+    for source_override in apic.interrupt_source_overrides.iter() {
+        let Some(line) = isa_lines.get_mut(usize::from(source_override.isa_source)) else {
+            continue;
+        };
+
+        // "same as bus" keeps the ISA defaults
+        line.gsi = source_override.global_system_interrupt;
+        match source_override.trigger_mode {
+            TriggerMode::SameAsBus => (),
+            TriggerMode::Edge => line.level_triggered = false,
+            TriggerMode::Level => line.level_triggered = true,
+        }
+        match source_override.polarity {
+            Polarity::SameAsBus => (),
+            Polarity::ActiveHigh => line.active_low = false,
+            Polarity::ActiveLow => line.active_low = true,
+        }
     }
+
+    ISA_LINES.call_once(|| isa_lines);
 }
 
-fn assign_irq_entry(index: InterruptIndex, mut ioapic: IoApic) {
+/// The line ISA IRQ `irq` is wired to, or `None` when there is no such IRQ.
+pub(in crate::interrupts) fn isa_line(irq: u8) -> Option<Line> {
+    ISA_LINES
+        .get()
+        .expect("I/O APICs weren't initialized yet")
+        .get(usize::from(irq))
+        .copied()
+}
+
+/// The GSI of `pin` on the first I/O APIC, when it has such a pin and no ISA IRQ is
+/// wired to it.
+pub fn first_io_apic_gsi(pin: u8) -> Option<u32> {
+    let io_apics = IO_APICS.lock();
+    let entry = io_apics.first().filter(|entry| pin < entry.pins)?;
+    let gsi = entry.gsi_base + u32::from(pin);
+
+    let isa_lines = ISA_LINES.get().expect("I/O APICs weren't initialized yet");
+    (!isa_lines.iter().any(|line| line.gsi == gsi)).then_some(gsi)
+}
+
+/// Deliver `line` to the local APIC with `vector`, through the I/O APIC pin of its GSI.
+///
+/// # Errors
+///
+/// When no I/O APIC handles the GSI, or the local APIC's id doesn't fit in the 8-bit
+/// destination of the I/O APICs.
+pub(in crate::interrupts) fn route(line: Line, vector: u8) -> Result<(), IrqError> {
+    let id = local::id();
+    let destination = u8::try_from(id).map_err(|_err| IrqError::UnreachableApic(id))?;
+
+    let mut io_apics = IO_APICS.lock();
+    let (entry, pin) = pin(&mut io_apics, line.gsi).ok_or(IrqError::UnknownGsi(line.gsi))?;
+
+    let mut flags = IrqFlags::empty();
+    flags.set(IrqFlags::LEVEL_TRIGGERED, line.level_triggered);
+    flags.set(IrqFlags::LOW_ACTIVE, line.active_low);
+
     let mut e = RedirectionTableEntry::default();
     e.set_mode(IrqMode::Fixed);
-    e.set_flags(IrqFlags::LEVEL_TRIGGERED | IrqFlags::LOW_ACTIVE);
-    e.set_vector(index.as_u8());
-    e.set_dest(
-        #[expect(clippy::unwrap_used)]
-        u8::try_from(LAPIC.lock().id()).unwrap(),
-    );
+    e.set_flags(flags);
+    e.set_vector(vector);
+    e.set_dest(destination);
 
     #[expect(unsafe_code)]
-    // SAFETY: Depends on the function arguments.
+    // SAFETY: The pin is one of the I/O APIC's.
+    unsafe {
+        entry.ioapic.set_table_entry(pin, e);
+    }
+    #[expect(unsafe_code)]
+    // SAFETY: The pin is one of the I/O APIC's.
     unsafe {
-        ioapic.set_table_entry(index.base_irq_index(), e);
+        entry.ioapic.enable_irq(pin);
     }
+
+    Ok(())
+}
+
+/// Stop delivering `gsi`, routed with `route`.
+pub(in crate::interrupts) fn mask(gsi: u32) {
+    let mut io_apics = IO_APICS.lock();
+    let Some((entry, pin)) = pin(&mut io_apics, gsi) else {
+        return;
+    };
+
     #[expect(unsafe_code)]
-    // SAFETY: Depends on the function arguments.
+    // SAFETY: The pin is one of the I/O APIC's.
     unsafe {
-        ioapic.enable_irq(index.base_irq_index());
+        entry.ioapic.disable_irq(pin);
     }
 }
+
+/// The I/O APIC handling `gsi`, and its pin.
+fn pin(io_apics: &mut [Entry], gsi: u32) -> Option<(&mut Entry, u8)> {
+    io_apics.iter_mut().find_map(|entry| {
+        let pin = u8::try_from(gsi.checked_sub(entry.gsi_base)?).ok()?;
+
+        (pin < entry.pins).then_some((entry, pin))
+    })
+}
@@ -1,11 +1,26 @@
-use spin::Mutex;
-use x2apic::lapic::{LocalApic, LocalApicBuilder};
+use spin::{once::Once, Mutex};
+use x2apic::lapic::{LocalApic, LocalApicBuilder, TimerDivide, TimerMode};
+use x86_64::registers::model_specific::Msr;
 
 use super::super::InterruptIndex;
+use crate::memory;
+
+/// Size of the memory mapped registers of a Local APIC.
+const LOCAL_APIC_REGISTERS_SIZE: usize = 0x1000;
+
+/// The TSC value at which the timer fires in TSC-deadline mode.
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+/// The timer counts down at the bus frequency divided by this.
+const TIMER_DIVIDE: TimerDivide = TimerDivide::Div16;
 
 // PERF: Mutex or deal with static mut?
 pub static LAPIC: Mutex<Local> = Mutex::new(Local { lapic: None });
 
+/// The local APIC's id, read when it's enabled, so it's known without locking `LAPIC`,
+/// which interrupt handlers lock.
+static ID: Once<u32> = Once::new();
+
 pub struct Local {
     lapic: Option<LocalApic>,
 }
@@ -19,13 +34,20 @@ impl Local {
     pub fn init(&mut self, local_apic_address: u64) {
         disable_8259();
 
-        let apic_virtual_address =
-            crate::memory::physical_to_virtual(x86_64::PhysAddr::new(local_apic_address));
+        // The Local APIC is used for the rest of the kernel's lifetime
+        let apic_virtual_address = memory::map_mmio(
+            x86_64::PhysAddr::new(local_apic_address),
+            LOCAL_APIC_REGISTERS_SIZE,
+            memory::CachePolicy::Uncached,
+        )
+        .expect("Failed to map Local APIC registers")
+        .leak();
 
         self.lapic = LocalApicBuilder::default()
             .timer_vector(InterruptIndex::Timer.as_usize())
             .error_vector(InterruptIndex::LapicErr.as_usize())
             .spurious_vector(InterruptIndex::Spurious.as_usize())
+            .timer_divide(TIMER_DIVIDE)
             .set_xapic_base(apic_virtual_address.as_u64())
             .build()
             .ok();
@@ -38,6 +60,8 @@ impl Local {
             #[expect(clippy::unwrap_used)]
             self.lapic.as_mut().unwrap().enable();
         }
+
+        ID.call_once(|| self.id());
     }
 
     pub fn disable(&mut self) {
@@ -58,6 +82,85 @@ impl Local {
         }
     }
 
+    /// Count how many timer ticks `wait` takes, with the timer's interrupt masked.
+    ///
+    /// The timer is left stopped.
+    pub fn measure_timer(&mut self, wait: impl FnOnce()) -> u32 {
+        #[expect(clippy::unwrap_used)]
+        let lapic = self.lapic.as_mut().unwrap();
+
+        #[expect(clippy::multiple_unsafe_ops_per_block)]
+        #[expect(unsafe_code)]
+        // SAFETY: The timer's interrupt is masked while it's measured.
+        unsafe {
+            lapic.disable_timer();
+            lapic.set_timer_mode(TimerMode::OneShot);
+            lapic.set_timer_initial(u32::MAX);
+        }
+
+        wait();
+
+        #[expect(clippy::multiple_unsafe_ops_per_block)]
+        #[expect(unsafe_code)]
+        // SAFETY: Same as above.
+        unsafe {
+            let elapsed = u32::MAX - lapic.timer_current();
+            lapic.set_timer_initial(0);
+
+            elapsed
+        }
+    }
+
+    /// Fire the timer interrupt every `ticks` timer ticks.
+    pub fn start_periodic_timer(&mut self, ticks: u32) {
+        self.start_timer(TimerMode::Periodic, ticks);
+    }
+
+    /// Fire the timer interrupt once, in `ticks` timer ticks.
+    pub fn start_one_shot_timer(&mut self, ticks: u32) {
+        self.start_timer(TimerMode::OneShot, ticks);
+    }
+
+    /// Fire the timer interrupt once, when the TSC reaches `deadline`.
+    ///
+    /// The CPU must support the TSC-deadline mode.
+    pub fn start_tsc_deadline_timer(&mut self, deadline: u64) {
+        self.start_timer(TimerMode::TscDeadline, 0);
+
+        #[expect(unsafe_code)]
+        // SAFETY: The timer is in TSC-deadline mode, so the MSR exists.
+        unsafe {
+            Msr::new(IA32_TSC_DEADLINE).write(deadline);
+        }
+    }
+
+    pub fn stop_timer(&mut self) {
+        #[expect(unsafe_code)]
+        // SAFETY: Trust my code and the hardware situation.
+        unsafe {
+            #[expect(clippy::unwrap_used)]
+            self.lapic.as_mut().unwrap().disable_timer();
+        }
+    }
+
+    fn start_timer(&mut self, mode: TimerMode, ticks: u32) {
+        #[expect(clippy::unwrap_used)]
+        let lapic = self.lapic.as_mut().unwrap();
+
+        #[expect(clippy::multiple_unsafe_ops_per_block)]
+        #[expect(unsafe_code)]
+        // SAFETY: The timer vector has a handler.
+        unsafe {
+            lapic.disable_timer();
+            lapic.set_timer_mode(mode);
+            lapic.enable_timer();
+            // the count starts when the initial count is written, 0 stops it
+            if !matches!(mode, TimerMode::TscDeadline) {
+                lapic.set_timer_initial(ticks);
+            }
+        }
+    }
+
     #[must_use]
     pub fn id(&self) -> u32 {
         #[expect(unsafe_code)]
@@ -69,6 +172,16 @@ impl Local {
     }
 }
 
+/// The local APIC's id.
+///
+/// # Panics
+///
+/// When the local APIC wasn't enabled yet.
+#[must_use]
+pub fn id() -> u32 {
+    *ID.get().expect("Local APIC wasn't enabled yet")
+}
+
 fn disable_8259() {
     use x86_64::instructions::port::Port;
 
@@ -0,0 +1,291 @@
+use core::fmt;
+
+use spin::once::Once;
+use x86_64::{
+    registers::mxcsr::{self, MxCsr},
+    structures::idt::{InterruptStackFrame, PageFaultErrorCode, SelectorErrorCode},
+    PrivilegeLevel, VirtAddr,
+};
+
+use crate::{backtrace, dbg_println};
+
+/// A CPU exception, with its error code decoded.
+#[derive(Debug, Clone, Copy)]
+pub enum Exception {
+    DivideError,
+    NonMaskableInterrupt,
+    Breakpoint,
+    Overflow,
+    BoundRangeExceeded,
+    InvalidOpcode,
+    DeviceNotAvailable,
+    InvalidTss(SelectorErrorCode),
+    SegmentNotPresent(SelectorErrorCode),
+    StackSegmentFault(SelectorErrorCode),
+    GeneralProtectionFault(SelectorErrorCode),
+    PageFault {
+        address: Option<VirtAddr>,
+        error_code: PageFaultErrorCode,
+    },
+    X87FloatingPoint,
+    AlignmentCheck,
+    /// With the exception flags set in `MXCSR`.
+    SimdFloatingPoint(MxCsr),
+}
+
+impl Exception {
+    /// A SIMD floating point exception, with the flags currently set in `MXCSR`.
+    #[must_use]
+    pub fn simd_floating_point() -> Self {
+        let flags = MxCsr::INVALID_OPERATION
+            | MxCsr::DENORMAL
+            | MxCsr::DIVIDE_BY_ZERO
+            | MxCsr::OVERFLOW
+            | MxCsr::UNDERFLOW
+            | MxCsr::PRECISION;
+
+        Self::SimdFloatingPoint(mxcsr::read() & flags)
+    }
+
+    /// Whether the instruction pointer is already past the instruction that raised it,
+    /// so it's safe to resume.
+    const fn is_trap(&self) -> bool {
+        matches!(
+            self,
+            Self::NonMaskableInterrupt | Self::Breakpoint | Self::Overflow
+        )
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let selector = |f: &mut fmt::Formatter<'_>, name, code: SelectorErrorCode| {
+            if code.is_null() {
+                write!(f, "{name} (null selector)")
+            } else {
+                write!(
+                    f,
+                    "{name} (selector {} in the {:?}{})",
+                    code.index(),
+                    code.descriptor_table(),
+                    if code.external() { ", external" } else { "" }
+                )
+            }
+        };
+
+        match *self {
+            Self::DivideError => write!(f, "DIVIDE ERROR"),
+            Self::NonMaskableInterrupt => write!(f, "NON MASKABLE INTERRUPT"),
+            Self::Breakpoint => write!(f, "BREAKPOINT"),
+            Self::Overflow => write!(f, "OVERFLOW"),
+            Self::BoundRangeExceeded => write!(f, "BOUND RANGE EXCEEDED"),
+            Self::InvalidOpcode => write!(f, "INVALID OPCODE"),
+            Self::DeviceNotAvailable => write!(f, "DEVICE NOT AVAILABLE"),
+            Self::InvalidTss(code) => selector(f, "INVALID TSS", code),
+            Self::SegmentNotPresent(code) => selector(f, "SEGMENT NOT PRESENT", code),
+            Self::StackSegmentFault(code) => selector(f, "STACK SEGMENT FAULT", code),
+            Self::GeneralProtectionFault(code) => selector(f, "GENERAL PROTECTION FAULT", code),
+            Self::PageFault {
+                address,
+                error_code,
+            } => write!(f, "PAGE FAULT at {address:?} ({error_code:?})"),
+            Self::X87FloatingPoint => write!(f, "X87 FLOATING POINT"),
+            Self::AlignmentCheck => write!(f, "ALIGNMENT CHECK"),
+            Self::SimdFloatingPoint(flags) => write!(f, "SIMD FLOATING POINT ({flags:?})"),
+        }
+    }
+}
+
+/// How an exception is resolved.
+#[derive(Debug, Clone, Copy)]
+enum Outcome {
+    /// Return to the interrupted code.
+    Resume,
+    /// Resume at the fixup address of the faulting instruction in the exception table.
+    Fixup(VirtAddr),
+    /// The fault comes from a user task, which is killed.
+    KillTask,
+    /// The fault comes from the kernel, which can't recover from it.
+    Panic,
+}
+
+/// Handles faults raised by user tasks, which must not return to them.
+static TASK_FAULT_HANDLER: Once<fn(Exception, &InterruptStackFrame)> = Once::new();
+
+/// Register the function killing the current task when it raises a fault.
+///
+/// It's called from the exception handler and must switch to another task, the
+/// faulting one can't be resumed.
+pub fn set_task_fault_handler(handler: fn(Exception, &InterruptStackFrame)) {
+    TASK_FAULT_HANDLER.call_once(|| handler);
+}
+
+/// Resolve `exception` raised with `stack_frame`: report traps and resume, fix up faults
+/// in the exception table, kill the faulting user task, or panic.
+pub(super) fn dispatch(exception: Exception, stack_frame: &mut InterruptStackFrame) {
+    match resolve(&exception, stack_frame) {
+        Outcome::Resume => (),
+        Outcome::Fixup(fixup) => {
+            #[expect(unsafe_code)]
+            // SAFETY: The exception table maps the instruction to its recovery code.
+            unsafe {
+                stack_frame
+                    .as_mut()
+                    .update(|frame| frame.instruction_pointer = fixup);
+            }
+        }
+        Outcome::KillTask => {
+            if let Some(handler) = TASK_FAULT_HANDLER.get() {
+                handler(exception, stack_frame);
+            }
+
+            backtrace::print_exception(stack_frame);
+            panic!(
+                "CPU EXCEPTION: {} in a task that can't be killed\n{:#?}",
+                exception, stack_frame
+            );
+        }
+        Outcome::Panic => {
+            backtrace::print_exception(stack_frame);
+            panic!("CPU EXCEPTION: {}\n{:#?}", exception, stack_frame);
+        }
+    }
+}
+
+fn resolve(exception: &Exception, stack_frame: &InterruptStackFrame) -> Outcome {
+    if exception.is_trap() {
+        dbg_println!("CPU EXCEPTION: {}\n{:#?}", exception, stack_frame);
+        return Outcome::Resume;
+    }
+
+    if stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3 {
+        return Outcome::KillTask;
+    }
+
+    search_exception_table(stack_frame.instruction_pointer).map_or(Outcome::Panic, Outcome::Fixup)
+}
+
+/// An instruction allowed to fault, and where to resume when it does.
+///
+/// Both are offsets from the field itself, so the table needs no relocations.
+#[repr(C)]
+struct ExceptionTableEntry {
+    instruction: i32,
+    fixup: i32,
+}
+
+impl ExceptionTableEntry {
+    fn instruction(&self) -> VirtAddr {
+        relative(
+            VirtAddr::from_ptr(&raw const self.instruction),
+            self.instruction,
+        )
+    }
+
+    fn fixup(&self) -> VirtAddr {
+        relative(VirtAddr::from_ptr(&raw const self.fixup), self.fixup)
+    }
+}
+
+/// Address `offset` bytes away from `addr`.
+const fn relative(addr: VirtAddr, offset: i32) -> VirtAddr {
+    VirtAddr::new_truncate(addr.as_u64().wrapping_add_signed(offset as i64))
+}
+
+// Entries are added to the `ex_table` section by the inline assembly of fallible accesses,
+// the linker defines its bounds.
+extern "C" {
+    static __start_ex_table: ExceptionTableEntry;
+    static __stop_ex_table: ExceptionTableEntry;
+}
+
+/// Fixup address of the instruction at `instruction_pointer`, if it's allowed to fault.
+pub(super) fn search_exception_table(instruction_pointer: VirtAddr) -> Option<VirtAddr> {
+    let start = &raw const __start_ex_table;
+    let end = &raw const __stop_ex_table;
+
+    #[expect(unsafe_code)]
+    // SAFETY: Both bounds are in the exception table.
+    let len = unsafe { end.offset_from(start) };
+
+    #[expect(unsafe_code)]
+    #[expect(clippy::cast_sign_loss)]
+    // SAFETY: The linker places the entries between the bounds.
+    let entries = unsafe { core::slice::from_raw_parts(start, len as usize) };
+
+    entries
+        .iter()
+        .find(|entry| entry.instruction() == instruction_pointer)
+        .map(ExceptionTableEntry::fixup)
+}
+
+/// Read the byte at `addr`, returning `None` instead of panicking when it faults,
+/// e.g. because it isn't mapped.
+///
+/// # Safety
+///
+/// Reading at `addr` must not have side effects, as for MMIO registers.
+#[expect(unsafe_code)]
+#[must_use]
+pub unsafe fn read_byte(addr: VirtAddr) -> Option<u8> {
+    let value: u8;
+    let read: u32;
+
+    #[expect(unsafe_code)]
+    // SAFETY: A fault on the read resumes after it, with `read` still 0.
+    unsafe {
+        core::arch::asm!(
+            "xor {read:e}, {read:e}",
+            "2:",
+            "mov {value}, byte ptr [{addr}]",
+            "mov {read:e}, 1",
+            "3:",
+            ".pushsection ex_table, \"aR\"",
+            ".balign 4",
+            ".long 2b - .",
+            ".long 3b - .",
+            ".popsection",
+            addr = in(reg) addr.as_u64(),
+            value = out(reg_byte) value,
+            read = out(reg) read,
+            options(nostack, readonly),
+        );
+    }
+
+    (read != 0).then_some(value)
+}
+
+/// Read the word at `addr`, returning `None` instead of panicking when it faults.
+///
+/// # Safety
+///
+/// Reading at `addr` must not have side effects, as for MMIO registers.
+#[expect(unsafe_code)]
+#[must_use]
+pub unsafe fn read_word(addr: VirtAddr) -> Option<u64> {
+    let value: u64;
+    let read: u32;
+
+    #[expect(unsafe_code)]
+    // SAFETY: A fault on the read resumes after it, with `read` still 0.
+    unsafe {
+        core::arch::asm!(
+            "xor {read:e}, {read:e}",
+            "2:",
+            "mov {value}, qword ptr [{addr}]",
+            "mov {read:e}, 1",
+            "3:",
+            ".pushsection ex_table, \"aR\"",
+            ".balign 4",
+            ".long 2b - .",
+            ".long 3b - .",
+            ".popsection",
+            addr = in(reg) addr.as_u64(),
+            value = out(reg) value,
+            read = out(reg) read,
+            options(nostack, readonly),
+        );
+    }
+
+    (read != 0).then_some(value)
+}
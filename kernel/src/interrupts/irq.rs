@@ -0,0 +1,286 @@
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::{
+    ops::RangeInclusive,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::{Mutex, RwLock};
+use x86_64::structures::idt::{HandlerFunc, InterruptStackFrame};
+
+use super::{
+    apic::{
+        io,
+        local::{self, LAPIC},
+    },
+    InterruptIndex,
+};
+use crate::dbg_println;
+
+/// Where an interrupt comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqSource {
+    /// A legacy ISA IRQ (0 to 15), mapped to its GSI, polarity and trigger mode by the
+    /// interrupt source overrides of the ACPI tables.
+    Isa(u8),
+    /// A global system interrupt, routed through the I/O APIC handling it.
+    ///
+    /// GSIs are level triggered and active low, as for PCI devices, and may be shared
+    /// by several devices.
+    Gsi(u32),
+    /// A global system interrupt that is edge triggered and active high, as for HPET
+    /// comparators.
+    EdgeGsi(u32),
+    /// A message signalled interrupt, which the device must be programmed to send with
+    /// `Irq::msi_message`.
+    Msi,
+}
+
+/// Priority of an interrupt, the APIC delivers higher priority vectors first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl IrqPriority {
+    /// Vectors allocated to interrupts of this priority.
+    const fn vectors(self) -> RangeInclusive<u8> {
+        match self {
+            Self::Low => 0x20..=0x6f,
+            Self::Normal => 0x70..=0xaf,
+            Self::High => 0xb0..=0xfe,
+        }
+    }
+}
+
+/// Handles an interrupt, in interrupt context: it must not block or allocate.
+pub trait IrqHandler: Send + Sync {
+    /// Returns whether the interrupt came from the handler's device, since every handler
+    /// of a shared line is called.
+    fn handle(&self) -> bool;
+}
+
+impl<F: Fn() -> bool + Send + Sync> IrqHandler for F {
+    fn handle(&self) -> bool {
+        self()
+    }
+}
+
+#[derive(Debug)]
+pub enum IrqError {
+    /// Every vector of the priority is used.
+    NoFreeVector,
+    /// There is no such ISA IRQ.
+    UnknownIsaIrq,
+    /// No I/O APIC handles the GSI.
+    UnknownGsi(u32),
+    /// The GSI is shared with handlers expecting another trigger mode or polarity.
+    MismatchedGsi(u32),
+    /// The local APIC's id doesn't fit in the interrupt's 8-bit destination.
+    UnreachableApic(u32),
+}
+
+/// A registered interrupt handler.
+#[derive(Debug, Clone, Copy)]
+pub struct Irq {
+    vector: u8,
+    source: IrqSource,
+    /// Identifies the handler among those of the vector.
+    id: u64,
+}
+
+impl Irq {
+    #[must_use]
+    pub const fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    /// The message the device must write to raise the interrupt, for `IrqSource::Msi`.
+    ///
+    /// Returns `None` for other sources, or when the local APIC's id doesn't fit in the
+    /// 8-bit destination of the message.
+    #[must_use]
+    pub fn msi_message(&self) -> Option<MsiMessage> {
+        let destination = u8::try_from(local::id()).ok()?;
+
+        (self.source == IrqSource::Msi).then(|| MsiMessage {
+            // fixed delivery to the local APIC, edge triggered
+            address: 0xfee0_0000 | (u64::from(destination) << 12_u32),
+            data: u32::from(self.vector),
+        })
+    }
+}
+
+/// The address and data of a message signalled interrupt.
+#[derive(Debug, Clone, Copy)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+/// Handlers of each vector, called in registration order.
+static HANDLERS: [RwLock<Vec<Registered>>; 256] = [const { RwLock::new(Vec::new()) }; 256];
+
+struct Registered {
+    /// Identifies the handler for `unregister_irq`.
+    id: u64,
+    handler: Box<dyn IrqHandler>,
+}
+
+static VECTORS: Mutex<Vectors> = Mutex::new(Vectors {
+    used: [false; 256],
+    gsis: BTreeMap::new(),
+});
+
+struct Vectors {
+    used: [bool; 256],
+    /// Vector and line of each routed GSI, shared by all its handlers.
+    gsis: BTreeMap<u32, (u8, io::Line)>,
+}
+
+impl Vectors {
+    fn allocate(&mut self, priority: IrqPriority) -> Option<u8> {
+        let vector = priority.vectors().find(|&vector| {
+            !self.used[usize::from(vector)] && !InterruptIndex::is_static(vector)
+        })?;
+        self.used[usize::from(vector)] = true;
+
+        Some(vector)
+    }
+}
+
+/// Call `handler` on interrupts from `source`, allocating a vector of `priority` for it.
+///
+/// An ISA IRQ and the GSI it's wired to are the same line. A line that already has
+/// handlers is shared: `handler` is added to them on the same vector, whatever
+/// `priority` is.
+///
+/// # Errors
+///
+/// When there is no free vector, the GSI isn't handled by any I/O APIC, or it's shared
+/// with handlers expecting another trigger mode or polarity.
+pub fn register_irq(
+    source: IrqSource,
+    handler: impl IrqHandler + 'static,
+    priority: IrqPriority,
+) -> Result<Irq, IrqError> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    let line = line(source)?;
+    let handler: Box<dyn IrqHandler> = Box::new(handler);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    // the handlers are locked by interrupts
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut vectors = VECTORS.lock();
+
+        if let Some(line) = line {
+            if let Some(&(vector, shared)) = vectors.gsis.get(&line.gsi) {
+                if shared != line {
+                    return Err(IrqError::MismatchedGsi(line.gsi));
+                }
+                HANDLERS[usize::from(vector)]
+                    .write()
+                    .push(Registered { id, handler });
+
+                return Ok(Irq { vector, source, id });
+            }
+        }
+
+        let vector = vectors.allocate(priority).ok_or(IrqError::NoFreeVector)?;
+        HANDLERS[usize::from(vector)]
+            .write()
+            .push(Registered { id, handler });
+
+        if let Some(line) = line {
+            if let Err(err) = io::route(line, vector) {
+                HANDLERS[usize::from(vector)].write().clear();
+                vectors.used[usize::from(vector)] = false;
+
+                return Err(err);
+            }
+
+            vectors.gsis.insert(line.gsi, (vector, line));
+        }
+
+        Ok(Irq { vector, source, id })
+    })
+}
+
+/// Remove the handler of `irq`, registered with `register_irq`.
+///
+/// The vector is freed with the last handler of the line, and the I/O APIC stops
+/// delivering its GSI. Does nothing when the handler was already removed.
+pub fn unregister_irq(irq: Irq) {
+    // the source was resolved when registering
+    let line = line(irq.source).ok().flatten();
+
+    let removed = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut vectors = VECTORS.lock();
+        let mut handlers = HANDLERS[usize::from(irq.vector)].write();
+
+        let index = handlers
+            .iter()
+            .position(|registered| registered.id == irq.id)?;
+        let removed = handlers.remove(index);
+
+        if handlers.is_empty() {
+            if let Some(line) = line {
+                io::mask(line.gsi);
+                vectors.gsis.remove(&line.gsi);
+            }
+            vectors.used[usize::from(irq.vector)] = false;
+        }
+
+        Some(removed)
+    });
+
+    // dropped with interrupts enabled
+    drop(removed);
+}
+
+/// The I/O APIC line of `source`, `None` for message signalled interrupts.
+fn line(source: IrqSource) -> Result<Option<io::Line>, IrqError> {
+    Ok(match source {
+        IrqSource::Isa(irq) => Some(io::isa_line(irq).ok_or(IrqError::UnknownIsaIrq)?),
+        IrqSource::Gsi(gsi) => Some(io::Line::pci(gsi)),
+        IrqSource::EdgeGsi(gsi) => Some(io::Line::edge(gsi)),
+        IrqSource::Msi => None,
+    })
+}
+
+fn dispatch(vector: u8) {
+    let handlers = HANDLERS[usize::from(vector)].read();
+
+    // every handler runs, since several devices may raise a shared line at once
+    let handled = handlers.iter().fold(false, |handled, registered| {
+        registered.handler.handle() | handled
+    });
+
+    if !handled {
+        dbg_println!(
+            "HARDWARE INTERRUPT: Unhandled interrupt on vector {}",
+            vector
+        );
+    }
+
+    LAPIC.lock().end_interrupt();
+}
+
+extern "x86-interrupt" fn irq_entry<const VECTOR: u8>(_stack_frame: InterruptStackFrame) {
+    dispatch(VECTOR);
+}
+
+macro_rules! irq_entries {
+    ($($class:literal)*) => {
+        [$(irq_entries!(@class $class 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)),*]
+    };
+    (@class $class:literal $($index:literal)*) => {
+        [$(irq_entry::<{ $class * 16 + $index }> as HandlerFunc),*]
+    };
+}
+
+/// Entry point of each vector from 32 on, by priority class, passing it to `dispatch`.
+pub(super) static IRQ_ENTRIES: [[HandlerFunc; 16]; 14] =
+    irq_entries!(2 3 4 5 6 7 8 9 10 11 12 13 14 15);
@@ -1,19 +1,23 @@
 use core::{
+    alloc::AllocError,
     pin::Pin,
     task::{Context, Poll},
 };
 
-use crossbeam_queue::ArrayQueue;
 use futures_util::{stream::Stream, task::AtomicWaker};
 use spin::once::Once;
 
-use crate::dbg_println;
+use super::{
+    irq::{IrqPriority, IrqSource},
+    register_irq,
+};
+use crate::{allocator::FixedQueue, dbg_println};
 
 const QUEUE_SIZE: usize = 100;
 
 static SCANCODE_STREAM_WAKER: AtomicWaker = AtomicWaker::new();
 
-static SCANCODE_QUEUE: Once<ArrayQueue<u8>> = Once::new();
+static SCANCODE_QUEUE: Once<FixedQueue<u8>> = Once::new();
 
 pub struct ScancodeStream {
     _private: (), // To prevent construction of the struct from outside of the module
@@ -29,11 +33,32 @@ impl ScancodeStream {
         if SCANCODE_QUEUE.is_completed() {
             panic!("ScancodeStream::new should only be called once");
         } else {
-            SCANCODE_QUEUE.call_once(|| ArrayQueue::new(QUEUE_SIZE));
+            SCANCODE_QUEUE.call_once(|| FixedQueue::new(QUEUE_SIZE));
         }
 
         Self { _private: () }
     }
+
+    /// Like `new`, but fails instead of aborting when the scancode queue can't be allocated.
+    ///
+    /// # Errors
+    ///
+    /// When the heap is out of memory.
+    ///
+    /// # Panics
+    ///
+    /// Only single `ScancodeStream` instance can be created.
+    pub fn try_new() -> Result<Self, AllocError> {
+        assert!(
+            !SCANCODE_QUEUE.is_completed(),
+            "ScancodeStream::try_new should only be called once"
+        );
+
+        let queue = FixedQueue::try_new(QUEUE_SIZE)?;
+        SCANCODE_QUEUE.call_once(|| queue);
+
+        Ok(Self { _private: () })
+    }
 }
 
 impl Stream for ScancodeStream {
@@ -57,10 +82,48 @@ impl Stream for ScancodeStream {
     }
 }
 
+/// The ISA IRQ of the PS/2 keyboard.
+const KEYBOARD_IRQ: u8 = 1;
+
+/// Register the keyboard interrupt handler.
+///
+/// # Panics
+///
+/// When the keyboard interrupt can't be routed.
+pub(crate) fn init() {
+    register_irq(
+        IrqSource::Isa(KEYBOARD_IRQ),
+        keyboard_interrupt_handler,
+        IrqPriority::Normal,
+    )
+    .expect("Failed to register the keyboard interrupt");
+}
+
+fn keyboard_interrupt_handler() -> bool {
+    let mut status_port = x86_64::instructions::port::PortReadOnly::<u8>::new(0x64);
+    // To read a byte from the keyboard’s data port.
+    let mut data_port = x86_64::instructions::port::PortReadOnly::new(0x60);
+
+    // the output buffer is empty when another device raised the line
+    #[expect(unsafe_code)]
+    // SAFETY: Reading the status of the PS/2 controller has no side effects.
+    if unsafe { status_port.read() } & 1 == 0 {
+        return false;
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: I/O port could have side effects that violate memory safety.
+    let scancode: u8 = unsafe { data_port.read() };
+
+    add_scancode(scancode);
+
+    true
+}
+
 /// Called by the keyboard interrupt handler.
 ///
 /// Must not block or allocate.
-pub(super) fn add_scancode(scancode: u8) {
+fn add_scancode(scancode: u8) {
     if let Some(queue) = SCANCODE_QUEUE.get() {
         match queue.push(scancode) {
             Ok(()) => SCANCODE_STREAM_WAKER.wake(),
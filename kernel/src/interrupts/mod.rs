@@ -1,22 +1,24 @@
 pub mod apic;
+pub mod exception;
+pub mod irq;
 pub mod keyboard;
 
+pub use irq::register_irq;
+
 use spin::Lazy;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode, SelectorErrorCode,
+};
 
-use crate::{dbg_println, gdt::IstIndex, hlt_loop};
+use crate::{backtrace, dbg_println, gdt::IstIndex, memory, time};
 use apic::local::LAPIC;
+use exception::Exception;
 
 pub static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
     let mut idt = InterruptDescriptorTable::new();
 
     // CPU Exceptions
-    #[expect(unsafe_code)]
-    unsafe {
-        idt.divide_error
-            .set_handler_fn(divide_error_handler)
-            .set_stack_index(IstIndex::DivideError.as_u16()); // 0
-    }
+    idt.divide_error.set_handler_fn(divide_error_handler); // 0
     idt.non_maskable_interrupt
         .set_handler_fn(non_maskable_interrupt_handler); // 2
     idt.breakpoint.set_handler_fn(breakpoint_handler); // 3
@@ -32,36 +34,14 @@ pub static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
             .set_handler_fn(double_fault_handler)
             .set_stack_index(IstIndex::DoubleFault.as_u16()); // 8
     }
-    #[expect(unsafe_code)]
-    unsafe {
-        idt.invalid_tss
-            .set_handler_fn(invalid_tss_handler)
-            .set_stack_index(IstIndex::InvalidTSS.as_u16()); // 10
-    }
-    #[expect(unsafe_code)]
-    unsafe {
-        idt.segment_not_present
-            .set_handler_fn(segment_not_present_handler)
-            .set_stack_index(IstIndex::SegmentNotPresent.as_u16()); // 11
-    }
-    #[expect(unsafe_code)]
-    unsafe {
-        idt.stack_segment_fault
-            .set_handler_fn(stack_segment_fault_handler)
-            .set_stack_index(IstIndex::StackSegmentFault.as_u16()); // 12
-    }
-    #[expect(unsafe_code)]
-    unsafe {
-        idt.general_protection_fault
-            .set_handler_fn(general_protection_fault_handler)
-            .set_stack_index(IstIndex::GeneralProtectionFault.as_u16()); // 13
-    }
-    #[expect(unsafe_code)]
-    unsafe {
-        idt.page_fault
-            .set_handler_fn(page_fault_handler)
-            .set_stack_index(IstIndex::InvalidTSS.as_u16()); // 14
-    }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler); // 10
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler); // 11
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler); // 12
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler); // 13
+    idt.page_fault.set_handler_fn(page_fault_handler); // 14
     idt.x87_floating_point
         .set_handler_fn(x87_floating_point_handler); // 15
     idt.alignment_check.set_handler_fn(alignment_check_handler); // 16
@@ -69,141 +49,184 @@ pub static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
     idt.simd_floating_point
         .set_handler_fn(simd_floating_point_handler); // 18
 
-    // Hardware Interrupts
+    // Hardware Interrupts, dispatched to the handlers registered with `register_irq`
+    for vector in 32..=u8::MAX {
+        idt[vector].set_handler_fn(
+            irq::IRQ_ENTRIES[usize::from(vector >> 4_u32) - 2][usize::from(vector & 0xf)],
+        );
+    }
+
+    // Local APIC interrupts
     idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
-    idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
     idt[InterruptIndex::LapicErr.as_u8()].set_handler_fn(lapic_err_handler);
     idt[InterruptIndex::Spurious.as_u8()].set_handler_fn(spurious_handler);
 
     idt
 });
 
-// FIX: Handle CPU Exceptions properly.
-
 // CPU Exceptions (0-30)
+// Faults resolve through `exception::dispatch`, since returning would re-execute the
+// faulting instruction forever.
 
 // 0
-extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+extern "x86-interrupt" fn divide_error_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::DivideError, &mut stack_frame);
 }
 
 // 2
-extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: NON MASKABLE INTERRUPT\n{:#?}", stack_frame);
+extern "x86-interrupt" fn non_maskable_interrupt_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::NonMaskableInterrupt, &mut stack_frame);
 }
 
 // 3
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+extern "x86-interrupt" fn breakpoint_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::Breakpoint, &mut stack_frame);
 }
 
 // 4
-extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: OVERFLOW\n{:#?}", stack_frame);
+extern "x86-interrupt" fn overflow_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::Overflow, &mut stack_frame);
 }
 
 // 5
-extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: BOUND RANGE EXCEEDED\n{:#?}", stack_frame);
+extern "x86-interrupt" fn bound_range_exceeded_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::BoundRangeExceeded, &mut stack_frame);
 }
 
 // 6
-extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+extern "x86-interrupt" fn invalid_opcode_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::InvalidOpcode, &mut stack_frame);
 }
 
 // 7
-extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: DEVICE NOT AVAILABLE\n{:#?}", stack_frame);
+extern "x86-interrupt" fn device_not_available_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::DeviceNotAvailable, &mut stack_frame);
 }
 
 // 8
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, code: u64) -> ! {
+    backtrace::print_exception(&stack_frame);
+
+    // Overflowing a stack that has no IST while pushing an exception frame double faults
+    if let Some(stack) = x86_64::registers::control::Cr2::read()
+        .ok()
+        .and_then(memory::overflowed_stack)
+    {
+        panic!(
+            "CPU EXCEPTION: DOUBLE FAULT {} (overflow of the {} stack)\n{:#?}",
+            code, stack, stack_frame
+        );
+    }
+
     panic!("CPU EXCEPTION: DOUBLE FAULT {}\n{:#?}", code, stack_frame);
 }
 
 // 10
-extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, code: u64) {
-    dbg_println!("CPU EXCEPTION: INVALID TSS {} \n{:#?}", code, stack_frame);
+extern "x86-interrupt" fn invalid_tss_handler(mut stack_frame: InterruptStackFrame, code: u64) {
+    exception::dispatch(
+        Exception::InvalidTss(SelectorErrorCode::new_truncate(code)),
+        &mut stack_frame,
+    );
 }
 
 // 11
-extern "x86-interrupt" fn segment_not_present_handler(stack_frame: InterruptStackFrame, code: u64) {
-    dbg_println!(
-        "CPU EXCEPTION: SEGMENT NOT PRESENT {}\n{:#?}",
-        code,
-        stack_frame
+extern "x86-interrupt" fn segment_not_present_handler(
+    mut stack_frame: InterruptStackFrame,
+    code: u64,
+) {
+    exception::dispatch(
+        Exception::SegmentNotPresent(SelectorErrorCode::new_truncate(code)),
+        &mut stack_frame,
     );
 }
 
 // 12
-extern "x86-interrupt" fn stack_segment_fault_handler(stack_frame: InterruptStackFrame, code: u64) {
-    dbg_println!(
-        "CPU EXCEPTION: STACK SEGMENT FAULT {}\n{:#?}",
-        code,
-        stack_frame
+extern "x86-interrupt" fn stack_segment_fault_handler(
+    mut stack_frame: InterruptStackFrame,
+    code: u64,
+) {
+    exception::dispatch(
+        Exception::StackSegmentFault(SelectorErrorCode::new_truncate(code)),
+        &mut stack_frame,
     );
 }
 
 // 13
 extern "x86-interrupt" fn general_protection_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     code: u64,
 ) {
-    dbg_println!(
-        "CPU EXCEPTION: GENERAL PROTECTION FAULT {}\n{:#?}",
-        code,
-        stack_frame
+    exception::dispatch(
+        Exception::GeneralProtectionFault(SelectorErrorCode::new_truncate(code)),
+        &mut stack_frame,
     );
 }
 
 // 14
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
-    dbg_println!("CPU EXCEPTION: PAGE FAULT");
-    dbg_println!(
-        "Accessed Address: {:?}",
-        x86_64::registers::control::Cr2::read()
-    );
-    dbg_println!("Error Code: {:?}", error_code);
-    dbg_println!("{:#?}", stack_frame);
+    let accessed_address = x86_64::registers::control::Cr2::read().ok();
+
+    if let Some(address) = accessed_address {
+        // Faults in lazy regions just need their page to be mapped
+        if memory::handle_page_fault(address, error_code) {
+            return;
+        }
+
+        // Accesses allowed to fault may probe guard pages
+        if let Some(stack) = memory::overflowed_stack(address).filter(|_| {
+            exception::search_exception_table(stack_frame.instruction_pointer).is_none()
+        }) {
+            backtrace::print_exception(&stack_frame);
+            panic!(
+                "CPU EXCEPTION: PAGE FAULT (overflow of the {} stack at {:?})\n{:#?}",
+                stack, address, stack_frame
+            );
+        }
+    }
 
-    hlt_loop();
+    exception::dispatch(
+        Exception::PageFault {
+            address: accessed_address,
+            error_code,
+        },
+        &mut stack_frame,
+    );
 }
 
 // 15
-extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: X86 FLOATING POINT\n{:#?}", stack_frame);
+extern "x86-interrupt" fn x87_floating_point_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::X87FloatingPoint, &mut stack_frame);
 }
 
 // 16
-extern "x86-interrupt" fn alignment_check_handler(stack_frame: InterruptStackFrame, code: u64) {
-    dbg_println!(
-        "CPU EXCEPTION: ALIGNMENT CHECK {}\n{:#?}",
-        code,
-        stack_frame
-    );
+extern "x86-interrupt" fn alignment_check_handler(
+    mut stack_frame: InterruptStackFrame,
+    _code: u64,
+) {
+    exception::dispatch(Exception::AlignmentCheck, &mut stack_frame);
 }
 
 // 17
 extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    backtrace::print_exception(&stack_frame);
     panic!("CPU EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame);
 }
 
 // 18
-extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
-    dbg_println!("CPU EXCEPTION: SIMD FLOATING POINT\n{:#?}", stack_frame);
+extern "x86-interrupt" fn simd_floating_point_handler(mut stack_frame: InterruptStackFrame) {
+    exception::dispatch(Exception::simd_floating_point(), &mut stack_frame);
 }
 
-// Hardware Interrupts - User Definable (32-119)
+// Hardware Interrupts - User Definable (32-255)
 
+/// Vectors of the local APIC's interrupts, the others are allocated by `register_irq`.
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = 32,
-    Keyboard = 33,
     LapicErr = 49,
     Spurious = 255,
 }
@@ -213,8 +236,11 @@ impl InterruptIndex {
         Self::Timer.as_u8()
     }
 
-    const fn base_irq_index(self) -> u8 {
-        self as u8 - Self::offset()
+    /// Whether `vector` is statically assigned.
+    const fn is_static(vector: u8) -> bool {
+        vector == Self::Timer.as_u8()
+            || vector == Self::LapicErr.as_u8()
+            || vector == Self::Spurious.as_u8()
     }
 
     const fn as_u8(self) -> u8 {
@@ -226,18 +252,7 @@ impl InterruptIndex {
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    LAPIC.lock().end_interrupt();
-}
-
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // To read a byte from the keyboard’s data port.
-    let mut port = x86_64::instructions::port::Port::new(0x60);
-
-    #[expect(unsafe_code)]
-    // SAFETY: I/O port could have side effects that violate memory safety.
-    let scancode: u8 = unsafe { port.read() };
-
-    keyboard::add_scancode(scancode);
+    time::on_timer_interrupt();
 
     LAPIC.lock().end_interrupt();
 }
@@ -1,5 +1,6 @@
 #![no_std]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![feature(allocator_api)]
 
 extern crate alloc;
@@ -7,12 +8,23 @@ extern crate alloc;
 mod acpi;
 mod allocator;
 pub mod async_tasking;
+pub mod backtrace;
 pub mod drivers;
 mod gdt;
 mod interrupts;
 mod memory;
+pub mod time;
 
-pub use interrupts::keyboard;
+#[cfg(feature = "heap-tracking")]
+pub use allocator::dump_allocations as dump_heap_allocations;
+pub use allocator::{
+    interrupt_pool, stats as heap_stats, BlockStats, BufferPool, HeapStats, PoolBuffer,
+};
+pub use interrupts::{exception, irq, keyboard};
+pub use memory::{
+    copy_from_user, copy_to_user, dma, enable_swap, map_mmio, swap_out, AddressSpace,
+    AddressSpaceError, CachePolicy, LazyRegion, MmioError, MmioMapping, SwapError,
+};
 use spin::Mutex;
 
 /// # Panics
@@ -20,9 +32,24 @@ use spin::Mutex;
 /// - When `physical_memory_offset` or `rsdp_addr` can't be fetched from `boot_info`.
 /// - When we can't map heap pages for some error.
 pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
+    // Handle exceptions from the start, on interrupt stacks that don't need memory
     gdt::init();
     interrupts::IDT.load();
 
+    // Load the kernel symbols first, so early panics are symbolized
+    {
+        let symbols = boot_info.ramdisk_addr.into_option().map(|addr| {
+            #[expect(unsafe_code)]
+            #[expect(clippy::cast_possible_truncation)]
+            // SAFETY: The bootloader maps the ramdisk, which is never written to.
+            unsafe {
+                core::slice::from_raw_parts(addr as *const u8, boot_info.ramdisk_len as usize)
+            }
+        });
+
+        backtrace::init(symbols, boot_info.kernel_image_offset);
+    }
+
     // PERF: Don't use static Mutexes for memory mapper and frame allocator.
     // Initialize Memory Mapping and Allocation
     {
@@ -40,8 +67,30 @@ pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
         unsafe {
             memory::init(physical_memory_offset, &boot_info.memory_regions);
         }
+
+        // Enforce W^X on the kernel image and enable the CPU protection features
+        #[expect(unsafe_code)]
+        // SAFETY: The kernel image values come from the bootloader.
+        unsafe {
+            memory::protect_kernel(
+                boot_info.kernel_addr,
+                boot_info.kernel_len,
+                boot_info.kernel_image_offset,
+            );
+        }
+
+        let frame_stats = memory::frame_stats();
+        dbg_println!(
+            "Physical memory: {} frames total, {} free, {} used",
+            frame_stats.total,
+            frame_stats.free,
+            frame_stats.used
+        );
     }
 
+    // Interrupt stacks are mapped with guard pages, so memory must be initialized first
+    gdt::use_guarded_stacks();
+
     // Initalize kernel heap memory
     allocator::init_heap().expect("Kernel heap initialization failed");
 
@@ -52,6 +101,8 @@ pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
             .as_ref()
             .expect("Can't find RSDP (Root System Description Pointer) address from boot_info"),
     );
+    keyboard::init();
+    time::init();
     x86_64::instructions::interrupts::enable();
 
     // Initialize Display
@@ -62,7 +62,8 @@ pub async fn print_keypresses() {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    kernel::dbg_print!("{}", info);
+    kernel::dbg_println!("{}", info);
+    kernel::backtrace::print();
 
     kernel::hlt_loop();
 }
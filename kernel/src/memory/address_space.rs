@@ -0,0 +1,406 @@
+use spin::once::Once;
+use x86_64::{
+    registers::control::{Cr3, Cr4, Cr4Flags},
+    structures::paging::{
+        mapper::{MapToError, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+    },
+    VirtAddr,
+};
+
+use super::{
+    get_memory_frame_allocator, get_memory_mapper, page_table, zero_frame, BuddyFrameAllocator,
+    PHYSICAL_MEMORY_OFFSET,
+};
+
+/// Level 4 entries of the lower half that the kernel doesn't use, so they hold user pages.
+static USER_ENTRIES: Once<[bool; 256]> = Once::new();
+
+#[derive(Debug)]
+pub enum AddressSpaceError {
+    /// The page is outside of the user part of the address space.
+    KernelPage,
+    Map(MapToError<Size4KiB>),
+    Unmap(UnmapError),
+    /// Copying user memory faulted, since it isn't mapped or writable.
+    Fault,
+}
+
+/// Give every unused level 4 entry of the higher half an empty level 3 table, and record
+/// which entries of the lower half are free for user pages.
+///
+/// The kernel's level 4 entries are copied into every address space, so from then on
+/// kernel mappings are shared by all of them.
+pub(super) fn init(mapper: &mut OffsetPageTable, frame_allocator: &mut BuddyFrameAllocator) {
+    let table = mapper.level_4_table_mut();
+
+    for entry in table.iter_mut().skip(256).filter(|entry| entry.is_unused()) {
+        let frame = frame_allocator
+            .allocate_frame()
+            .expect("Failed to allocate the kernel's level 3 page tables");
+
+        #[expect(unsafe_code)]
+        // SAFETY: The frame was just allocated.
+        unsafe {
+            zero_frame(frame);
+        }
+
+        entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    }
+
+    let mut user_entries = [false; 256];
+    for (user, entry) in user_entries.iter_mut().zip(table.iter()) {
+        *user = entry.is_unused();
+    }
+
+    USER_ENTRIES.call_once(|| user_entries);
+}
+
+/// A separate set of page tables, to run programs isolated from each other.
+///
+/// It shares the kernel's level 4 entries, so the kernel stays mapped whichever address
+/// space is active. The rest of the lower half holds user pages, owned by the address
+/// space: they are unmapped and their page tables freed when it's dropped.
+#[derive(Debug)]
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    /// Create an address space with no user pages.
+    ///
+    /// Returns `None` when there is no free frame for its level 4 table.
+    #[must_use]
+    pub fn new() -> Option<Self> {
+        let level_4_frame = get_memory_frame_allocator().lock().allocate_frame()?;
+
+        let mut address_space = Self { level_4_frame };
+        let kernel_mapper = get_memory_mapper().lock();
+
+        // shares the kernel's entries, the others are empty
+        address_space
+            .level_4_table()
+            .clone_from(kernel_mapper.level_4_table());
+
+        Some(address_space)
+    }
+
+    /// Map `page` to a newly allocated zeroed frame with `flags`, made user accessible.
+    ///
+    /// # Errors
+    ///
+    /// When `page` isn't a user page, there is no free frame, or it's already mapped.
+    pub fn map(
+        &mut self,
+        page: Page<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), AddressSpaceError> {
+        if !is_user_page(page) {
+            return Err(AddressSpaceError::KernelPage);
+        }
+
+        let mut frame_allocator = get_memory_frame_allocator().lock();
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(AddressSpaceError::Map(MapToError::FrameAllocationFailed))?;
+
+        #[expect(unsafe_code)]
+        // SAFETY: The frame was just allocated.
+        unsafe {
+            zero_frame(frame);
+        }
+
+        let is_active = self.is_active();
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let parent_flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+        #[expect(unsafe_code)]
+        // SAFETY: User pages are only accessed by the programs running in the address space.
+        let result = unsafe {
+            self.mapper().map_to_with_table_flags(
+                page,
+                frame,
+                flags,
+                parent_flags,
+                &mut *frame_allocator,
+            )
+        };
+
+        match result {
+            Ok(flush) if is_active => flush.flush(),
+            Ok(flush) => flush.ignore(),
+            Err(err) => {
+                #[expect(unsafe_code)]
+                // SAFETY: The frame wasn't mapped.
+                unsafe {
+                    frame_allocator.deallocate_frame(frame);
+                }
+
+                return Err(AddressSpaceError::Map(err));
+            }
+        }
+
+        frame_allocator.add_mapping(frame);
+
+        Ok(())
+    }
+
+    /// Unmap the user page `page`, freeing its frame when it was its last mapping.
+    ///
+    /// # Errors
+    ///
+    /// When `page` isn't a mapped user page.
+    pub fn unmap(&mut self, page: Page<Size4KiB>) -> Result<(), AddressSpaceError> {
+        if !is_user_page(page) {
+            return Err(AddressSpaceError::KernelPage);
+        }
+
+        let is_active = self.is_active();
+        let (frame, flush) = self
+            .mapper()
+            .unmap(page)
+            .map_err(AddressSpaceError::Unmap)?;
+
+        if is_active {
+            flush.flush();
+        } else {
+            flush.ignore();
+        }
+
+        get_memory_frame_allocator().lock().remove_mapping(frame);
+
+        Ok(())
+    }
+
+    /// Switch to this address space.
+    ///
+    /// # Safety
+    ///
+    /// The address space must not be dropped while it's active, and nothing may use user
+    /// pages of the previously active one until it's switched back.
+    #[expect(unsafe_code)]
+    pub unsafe fn activate(&self) {
+        let (_, flags) = Cr3::read();
+
+        #[expect(unsafe_code)]
+        // SAFETY: The kernel is mapped the same in every address space.
+        unsafe {
+            Cr3::write(self.level_4_frame, flags);
+        }
+    }
+
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    fn level_4_table(&mut self) -> &mut PageTable {
+        #[expect(unsafe_code)]
+        // SAFETY: The level 4 table is owned by the address space.
+        unsafe {
+            page_table(self.level_4_frame)
+        }
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let physical_memory_offset = *PHYSICAL_MEMORY_OFFSET
+            .get()
+            .expect("Memory Offset wasn't initialized yet");
+        let level_4_table = self.level_4_table();
+
+        #[expect(unsafe_code)]
+        // SAFETY: The physical memory is mapped at the offset.
+        unsafe {
+            OffsetPageTable::new(level_4_table, physical_memory_offset)
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "Dropped the active address space");
+
+        let user_entries = USER_ENTRIES
+            .get()
+            .expect("Address spaces weren't initialized yet");
+        let mut frame_allocator = get_memory_frame_allocator().lock();
+
+        for (entry, _) in self
+            .level_4_table()
+            .iter()
+            .zip(user_entries)
+            .filter(|&(_, &user)| user)
+        {
+            if let Ok(frame) = entry.frame() {
+                free_table(&mut frame_allocator, frame, 3);
+            }
+        }
+
+        #[expect(unsafe_code)]
+        // SAFETY: The address space isn't active, so its level 4 table is unused.
+        unsafe {
+            frame_allocator.deallocate_frame(self.level_4_frame);
+        }
+    }
+}
+
+/// Whether `page` is in a level 4 entry free for user pages.
+fn is_user_page(page: Page<Size4KiB>) -> bool {
+    USER_ENTRIES
+        .get()
+        .expect("Address spaces weren't initialized yet")
+        .get(usize::from(page.p4_index()))
+        .is_some_and(|&user| user)
+}
+
+/// Whether `start..start + len` is entirely in level 4 entries free for user pages.
+fn is_user_range(start: VirtAddr, len: usize) -> bool {
+    let Some(last) = start
+        .as_u64()
+        .checked_add(len.max(1) as u64 - 1)
+        .and_then(|last| VirtAddr::try_new(last).ok())
+    else {
+        return false;
+    };
+    let user_entries = USER_ENTRIES
+        .get()
+        .expect("Address spaces weren't initialized yet");
+
+    (usize::from(start.p4_index())..=usize::from(last.p4_index()))
+        .all(|index| user_entries.get(index).is_some_and(|&user| user))
+}
+
+/// Copy `src.len()` bytes from `src` to the user memory at `dst`, in the active
+/// address space.
+///
+/// This is the only way for the kernel to write user memory, which SMAP forbids otherwise.
+///
+/// # Errors
+///
+/// When `dst` isn't user memory, or isn't mapped writable.
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), AddressSpaceError> {
+    if !is_user_range(dst, src.len()) {
+        return Err(AddressSpaceError::KernelPage);
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: `dst` is user memory, which the kernel never references.
+    let not_copied = unsafe { copy_user(dst.as_mut_ptr(), src.as_ptr(), src.len()) };
+
+    if not_copied == 0 {
+        Ok(())
+    } else {
+        Err(AddressSpaceError::Fault)
+    }
+}
+
+/// Copy `dst.len()` bytes from the user memory at `src` to `dst`, in the active
+/// address space.
+///
+/// This is the only way for the kernel to read user memory, which SMAP forbids otherwise.
+///
+/// # Errors
+///
+/// When `src` isn't user memory, or isn't mapped.
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), AddressSpaceError> {
+    if !is_user_range(src, dst.len()) {
+        return Err(AddressSpaceError::KernelPage);
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: `src` is user memory, which the kernel never references.
+    let not_copied = unsafe { copy_user(dst.as_mut_ptr(), src.as_ptr(), dst.len()) };
+
+    if not_copied == 0 {
+        Ok(())
+    } else {
+        Err(AddressSpaceError::Fault)
+    }
+}
+
+/// Copy `len` bytes from `src` to `dst` with SMAP lifted, returning how many bytes
+/// weren't copied because an access faulted.
+///
+/// # Safety
+///
+/// One of the ranges must be kernel memory valid for the access, and the other user memory.
+#[expect(unsafe_code)]
+unsafe fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize {
+    let smap = Cr4::read().contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION);
+    let not_copied: usize;
+
+    #[expect(unsafe_code)]
+    // SAFETY: A fault on the copy resumes after it, with the remaining count in `rcx`.
+    // `stac` and `clac` only exist with SMAP.
+    unsafe {
+        core::arch::asm!(
+            "test {smap}, {smap}",
+            "jz 2f",
+            "stac",
+            "2:",
+            "3:",
+            "rep movsb",
+            "4:",
+            "test {smap}, {smap}",
+            "jz 5f",
+            "clac",
+            "5:",
+            ".pushsection ex_table, \"aR\"",
+            ".balign 4",
+            ".long 3b - .",
+            ".long 4b - .",
+            ".popsection",
+            smap = in(reg_byte) u8::from(smap),
+            inout("rcx") len => not_copied,
+            inout("rdi") dst => _,
+            inout("rsi") src => _,
+            options(nostack),
+        );
+    }
+
+    not_copied
+}
+
+/// Free the page table in `frame` at `level` (1 to 3), along with the tables under it,
+/// dropping the mappings of the frames it maps.
+fn free_table(frame_allocator: &mut BuddyFrameAllocator, frame: PhysFrame, level: u8) {
+    #[expect(unsafe_code)]
+    // SAFETY: The table belongs to an address space being dropped.
+    let table = unsafe { page_table(frame) };
+
+    for entry in table.iter() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        // in level 1 entries, the bit of `HUGE_PAGE` is the PAT bit instead
+        match level {
+            1 => {
+                frame_allocator
+                    .remove_mapping(PhysFrame::<Size4KiB>::containing_address(entry.addr()));
+            }
+            _ if !flags.contains(PageTableFlags::HUGE_PAGE) => free_table(
+                frame_allocator,
+                PhysFrame::containing_address(entry.addr()),
+                level - 1,
+            ),
+            2 => {
+                frame_allocator
+                    .remove_mapping(PhysFrame::<Size2MiB>::containing_address(entry.addr()));
+            }
+            _ => {
+                frame_allocator
+                    .remove_mapping(PhysFrame::<Size1GiB>::containing_address(entry.addr()));
+            }
+        }
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: Nothing is mapped through the table anymore.
+    unsafe {
+        frame_allocator.deallocate_frame(frame);
+    }
+}
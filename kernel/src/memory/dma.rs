@@ -0,0 +1,221 @@
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use x86_64::{
+    structures::paging::{PageSize, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::{
+    get_memory_frame_allocator, lazy, map_mmio, set_cache_policy, translate_addr, CachePolicy,
+    MmioError, MmioMapping, PAGE_SIZE,
+};
+use crate::dbg_println;
+
+/// Limit for devices that can only address the low 4 GiB of physical memory.
+pub const LIMIT_32BIT: PhysAddr = PhysAddr::new_truncate(1 << 32);
+
+#[derive(Debug)]
+pub enum DmaError {
+    /// No free contiguous frames satisfy the size, alignment and limit.
+    OutOfMemory,
+    Map(MmioError),
+}
+
+/// Physically contiguous memory shared with a device, freed when dropped.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    phys: PhysAddr,
+    mapping: MmioMapping,
+    /// Memory type of the buffer and of its alias in the physical memory mapping.
+    cache_policy: CachePolicy,
+}
+
+impl DmaBuffer {
+    /// Physical address of the buffer, to be given to the device.
+    #[must_use]
+    pub const fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    #[must_use]
+    pub const fn virt_addr(&self) -> VirtAddr {
+        self.mapping.virt_addr()
+    }
+
+    #[must_use]
+    pub const fn as_mut_ptr<T>(&self) -> *mut T {
+        self.mapping.as_mut_ptr()
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.mapping.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.mapping.is_empty()
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if self.cache_policy != CachePolicy::WriteBack {
+            #[expect(unsafe_code)]
+            // SAFETY: The frames are RAM, and only mapped by the buffer and their alias.
+            let restored =
+                unsafe { set_cache_policy(self.virt_addr(), self.len(), CachePolicy::WriteBack) };
+
+            // frames with another memory type in their alias must not be reused
+            if let Err(err) = restored {
+                dbg_println!(
+                    "DMA: leaking a buffer whose memory type can't be restored: {:?}",
+                    err
+                );
+                return;
+            }
+        }
+
+        // The mapping is dropped after this, and frees the frames with their last mapping
+        release_frames(self.phys, self.len());
+    }
+}
+
+/// Allocate a zeroed buffer of `size` bytes, physically contiguous, aligned to `align`
+/// (a power of 2) and ending at or below `limit`.
+///
+/// The buffer is mapped with `cache_policy`, usually `Uncached` for descriptors
+/// and `WriteCombining` for data the CPU only writes. Its alias in the physical memory
+/// mapping gets the same memory type until it's dropped.
+///
+/// # Errors
+///
+/// When there are no such free frames, or they can't be mapped.
+pub fn alloc_coherent(
+    size: usize,
+    align: usize,
+    limit: PhysAddr,
+    cache_policy: CachePolicy,
+) -> Result<DmaBuffer, DmaError> {
+    let size = size.max(1);
+    let count = size.div_ceil(PAGE_SIZE);
+
+    let phys = {
+        let mut frame_allocator = get_memory_frame_allocator().lock();
+
+        let start = frame_allocator
+            .allocate_contiguous(count, align as u64, limit)
+            .ok_or(DmaError::OutOfMemory)?;
+
+        // The buffer holds a reference to its frames, so they outlive its mapping
+        for frame in PhysFrame::range(start, start + count as u64) {
+            frame_allocator.add_mapping(frame);
+        }
+
+        start.start_address()
+    };
+
+    let mapping = map_mmio(phys, size, cache_policy).map_err(|err| {
+        release_frames(phys, size);
+
+        DmaError::Map(err)
+    })?;
+    let buffer = DmaBuffer {
+        phys,
+        mapping,
+        cache_policy,
+    };
+
+    // On failure, dropping the buffer restores the memory type of the updated alias pages
+    if cache_policy != CachePolicy::WriteBack {
+        #[expect(unsafe_code)]
+        // SAFETY: The frames are RAM, and only mapped by the buffer and their alias.
+        unsafe { set_cache_policy(buffer.virt_addr(), size, cache_policy) }
+            .map_err(|err| DmaError::Map(err.into()))?;
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: The frames were just allocated and only this mapping uses them.
+    unsafe {
+        core::ptr::write_bytes(buffer.as_mut_ptr::<u8>(), 0, size);
+    }
+
+    Ok(buffer)
+}
+
+/// Drop the buffer's reference to its frames, freeing them when they aren't mapped anymore.
+fn release_frames(phys: PhysAddr, size: usize) {
+    let start = PhysFrame::<Size4KiB>::containing_address(phys);
+    let mut frame_allocator = get_memory_frame_allocator().lock();
+
+    for frame in PhysFrame::range(start, start + size.div_ceil(PAGE_SIZE) as u64) {
+        frame_allocator.remove_mapping(frame);
+    }
+}
+
+/// A physically contiguous part of a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub phys: PhysAddr,
+    pub len: usize,
+}
+
+/// The physical segments of a buffer mapped in kernel memory, for streaming DMA.
+///
+/// The buffer is borrowed as long as the list lives, so it's neither freed nor moved
+/// while the device uses it.
+#[derive(Debug)]
+pub struct ScatterGatherList<'buffer> {
+    segments: Vec<Segment>,
+    buffer: PhantomData<&'buffer mut [u8]>,
+}
+
+impl<'buffer> ScatterGatherList<'buffer> {
+    /// Collect the physical segments of `buffer`, merging physically contiguous pages.
+    ///
+    /// Returns `None` when a page of the buffer isn't mapped, or is part of a lazy region,
+    /// whose pages may be swapped out while the device uses them.
+    #[must_use]
+    pub fn new(buffer: &'buffer mut [u8]) -> Option<Self> {
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut addr = VirtAddr::from_ptr(buffer.as_ptr());
+        let end = addr + buffer.len() as u64;
+
+        if lazy::overlaps(addr, end) {
+            return None;
+        }
+
+        while addr < end {
+            let page_end = (addr + 1_u64).align_up(Size4KiB::SIZE).min(end);
+            #[expect(clippy::cast_possible_truncation)]
+            let len = (page_end - addr) as usize;
+            let phys = translate_addr(addr)?;
+
+            match segments.last_mut() {
+                Some(last) if last.phys + last.len as u64 == phys => last.len += len,
+                _ => segments.push(Segment { phys, len }),
+            }
+
+            addr = page_end;
+        }
+
+        Some(Self {
+            segments,
+            buffer: PhantomData,
+        })
+    }
+
+    #[must_use]
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Whether every segment ends at or below `limit`.
+    #[must_use]
+    pub fn is_below(&self, limit: PhysAddr) -> bool {
+        self.segments
+            .iter()
+            .all(|segment| segment.phys + segment.len as u64 <= limit)
+    }
+}
@@ -0,0 +1,468 @@
+use core::ops::Range;
+
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::PAGE_SIZE;
+
+/// The largest block that can be allocated at once is `2^MAX_ORDER` frames (1 GiB).
+pub const MAX_ORDER: usize = 18;
+
+/// Bookkeeping state of a single physical frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameState {
+    /// Not usable memory (firmware, MMIO, holes, the allocator metadata itself).
+    Reserved,
+    /// Handed out by the allocator, with the number of page mappings referencing it.
+    Allocated(u16),
+    /// Part of a free block, but not its first frame.
+    Free,
+    /// First frame of a free block of the given order, linked in `free_lists[order]`.
+    FreeHead(u8),
+}
+
+/// Node of the doubly linked free lists, stored inside the free frames themselves.
+struct FreeBlock {
+    prev: Option<PhysAddr>,
+    next: Option<PhysAddr>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    pub used: usize,
+}
+
+/// A binary buddy `FrameAllocator` built once from the bootloader's memory regions.
+///
+/// Free blocks are kept in one list per order, so allocation and deallocation are
+/// `O(MAX_ORDER)`, and every block is naturally aligned to its own size.
+pub struct BuddyFrameAllocator {
+    physical_memory_offset: VirtAddr,
+    /// One entry for every frame from physical address 0 up to the end of usable memory.
+    frames: &'static mut [FrameState],
+    free_lists: [Option<PhysAddr>; MAX_ORDER + 1],
+    total_frames: usize,
+    free_frames: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// Create a `FrameAllocator` from the passed memory regions.
+    ///
+    /// The frame metadata is placed at the start of the first usable region that is large
+    /// enough to hold it, and those frames are never handed out.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed memory map is valid, all frames that are
+    /// marked as `USABLE` in it are really unused, and the complete physical memory is
+    /// mapped at `physical_memory_offset`.
+    ///
+    /// With the `memtest` feature, the usable frames are tested first, which overwrites them.
+    ///
+    /// # Panics
+    ///
+    /// When there is no usable region large enough to hold the frame metadata.
+    #[expect(unsafe_code)]
+    pub unsafe fn init(
+        memory_regions: &'static MemoryRegions,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
+        let usable_ranges = || {
+            memory_regions
+                .iter()
+                .filter(|r| r.kind == MemoryRegionKind::Usable)
+                .map(|r| {
+                    x86_64::align_up(r.start, PAGE_SIZE as u64)
+                        ..x86_64::align_down(r.end, PAGE_SIZE as u64)
+                })
+                .filter(|r| !r.is_empty())
+        };
+
+        #[expect(clippy::cast_possible_truncation)]
+        #[expect(clippy::integer_division)]
+        let frame_count =
+            (usable_ranges().map(|r| r.end).max().unwrap_or(0) / PAGE_SIZE as u64) as usize;
+
+        let metadata_size = x86_64::align_up(
+            (frame_count * size_of::<FrameState>()) as u64,
+            PAGE_SIZE as u64,
+        );
+        let metadata_start = usable_ranges()
+            .find(|r| r.end - r.start >= metadata_size)
+            .expect("No usable memory region is large enough for the frame allocator metadata")
+            .start;
+        let metadata = metadata_start..metadata_start + metadata_size;
+
+        let frames_ptr = (physical_memory_offset + metadata_start).as_mut_ptr::<FrameState>();
+        for i in 0..frame_count {
+            #[expect(clippy::multiple_unsafe_ops_per_block)]
+            #[expect(unsafe_code)]
+            // SAFETY: The metadata range is usable memory, so it's unused and mapped.
+            unsafe {
+                frames_ptr.add(i).write(FrameState::Reserved);
+            }
+        }
+
+        let mut allocator = Self {
+            physical_memory_offset,
+            #[expect(unsafe_code)]
+            // SAFETY: All `frame_count` entries were initialized above.
+            frames: unsafe { core::slice::from_raw_parts_mut(frames_ptr, frame_count) },
+            free_lists: [None; MAX_ORDER + 1],
+            total_frames: 0,
+            free_frames: 0,
+        };
+
+        for range in usable_ranges() {
+            // Skip the frames holding the metadata.
+            if range.contains(&metadata.start) {
+                allocator.add_usable_range(range.start, metadata.start);
+                allocator.add_usable_range(metadata.end, range.end);
+            } else {
+                allocator.add_usable_range(range.start, range.end);
+            }
+        }
+
+        allocator
+    }
+
+    /// Hand the usable frames in `start..end` to the allocator, except the faulty ones
+    /// when the `memtest` feature is enabled.
+    fn add_usable_range(&mut self, start: u64, end: u64) {
+        #[cfg(feature = "memtest")]
+        {
+            let mut free_start = start;
+            super::memtest::test_range(self.physical_memory_offset, start..end, |bad| {
+                self.add_free_range(free_start, bad);
+                free_start = bad + PAGE_SIZE as u64;
+            });
+
+            self.add_free_range(free_start, end);
+        }
+
+        #[cfg(not(feature = "memtest"))]
+        self.add_free_range(start, end);
+    }
+
+    /// Hand the frames in `start..end` (page aligned physical addresses) to the allocator.
+    fn add_free_range(&mut self, start: u64, end: u64) {
+        #[expect(clippy::cast_possible_truncation)]
+        #[expect(clippy::integer_division)]
+        let (mut index, end) = (
+            (start / PAGE_SIZE as u64) as usize,
+            (end / PAGE_SIZE as u64) as usize,
+        );
+
+        while index < end {
+            // Biggest block that is aligned at `index` and still fits in the range.
+            let mut order = (index.trailing_zeros() as usize).min(MAX_ORDER);
+            while index + (1 << order) > end {
+                order -= 1;
+            }
+
+            self.frames[index..index + (1 << order)].fill(FrameState::Allocated(0));
+            self.total_frames += 1 << order;
+            self.release(index, order);
+
+            index += 1 << order;
+        }
+    }
+
+    /// Allocate a block of `2^order` contiguous frames aligned to its own size.
+    pub fn allocate(&mut self, order: usize) -> Option<PhysFrame> {
+        self.allocate_where(order, |_| true)
+    }
+
+    /// Allocate `count` contiguous frames, starting at an address aligned to `align`
+    /// (a power of 2), and ending at or below `limit`.
+    ///
+    /// The frames are freed one by one with `deallocate(frame, 0)`. Returns `None` when
+    /// `count` is 0.
+    pub fn allocate_contiguous(
+        &mut self,
+        count: usize,
+        align: u64,
+        limit: PhysAddr,
+    ) -> Option<PhysFrame> {
+        if count == 0 {
+            return None;
+        }
+
+        let order = (count.next_power_of_two().trailing_zeros() as usize).max(
+            align
+                .trailing_zeros()
+                .saturating_sub(PAGE_SIZE.trailing_zeros()) as usize,
+        );
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        #[expect(clippy::cast_possible_truncation)]
+        #[expect(clippy::integer_division)]
+        let limit_index = (limit.as_u64() / PAGE_SIZE as u64) as usize;
+
+        let frame = self.allocate_where(order, |index| index + (1 << order) <= limit_index)?;
+
+        // Give back the frames past `count`
+        let start = Self::frame_to_index(frame);
+        for index in start + count..start + (1 << order) {
+            self.release(index, 0);
+        }
+
+        Some(frame)
+    }
+
+    /// Allocate a block of `2^order` frames from the first free block accepted by `fits`.
+    ///
+    /// The allocated block is the start of the free block it's split from.
+    fn allocate_where(&mut self, order: usize, fits: impl Fn(usize) -> bool) -> Option<PhysFrame> {
+        let (block, mut current_order) =
+            (order..=MAX_ORDER).find_map(|o| self.find_free(o, &fits).map(|block| (block, o)))?;
+
+        self.remove_free(block, current_order);
+
+        // Split the block, giving back the upper halves.
+        while current_order > order {
+            current_order -= 1;
+            self.push_free(block + (1 << current_order), current_order);
+        }
+
+        self.frames[block..block + (1 << order)].fill(FrameState::Allocated(0));
+        self.free_frames -= 1 << order;
+
+        Some(Self::index_to_frame(block))
+    }
+
+    /// Return a block of `2^order` frames previously returned by `allocate`.
+    ///
+    /// # Safety
+    ///
+    /// The block must have been allocated with the same `order` and must be unused.
+    #[expect(unsafe_code)]
+    pub unsafe fn deallocate(&mut self, frame: PhysFrame, order: usize) {
+        let index = Self::frame_to_index(frame);
+
+        assert!(
+            self.frames[index..index + (1 << order)]
+                .iter()
+                .all(|&s| s == FrameState::Allocated(0)),
+            "Deallocating a frame that is not allocated: {frame:?}"
+        );
+
+        self.release(index, order);
+    }
+
+    /// Allocate a naturally aligned frame of the given page size.
+    pub fn allocate_sized<S: PageSize>(&mut self) -> Option<PhysFrame<S>> {
+        let frame = self.allocate(order_of::<S>())?;
+
+        Some(PhysFrame::containing_address(frame.start_address()))
+    }
+
+    /// Record a new page mapping of `frame`.
+    ///
+    /// Only frames handed out by the allocator are counted, others (firmware, MMIO) are ignored.
+    ///
+    /// # Panics
+    ///
+    /// When the frame is mapped more than `u16::MAX` times.
+    pub fn add_mapping<S: PageSize>(&mut self, frame: PhysFrame<S>) {
+        for index in Self::frame_indices(frame) {
+            if let Some(&mut FrameState::Allocated(ref mut mappings)) = self.frames.get_mut(index) {
+                *mappings = mappings
+                    .checked_add(1)
+                    .expect("Too many mappings of a single frame");
+            }
+        }
+    }
+
+    /// Remove a page mapping of `frame`, freeing the frame when it was the last one.
+    ///
+    /// Frames that are not owned by the allocator, or whose mappings were never
+    /// recorded with `add_mapping` are left untouched. The 4 KiB frames making up a
+    /// huge frame are counted and freed separately.
+    ///
+    /// Returns `true` when any frame was returned to the free lists.
+    pub fn remove_mapping<S: PageSize>(&mut self, frame: PhysFrame<S>) -> bool {
+        let mut freed = false;
+
+        for index in Self::frame_indices(frame) {
+            if let Some(&mut FrameState::Allocated(ref mut mappings)) = self.frames.get_mut(index) {
+                if *mappings == 0 {
+                    continue;
+                }
+
+                *mappings -= 1;
+                if *mappings == 0 {
+                    self.release(index, 0);
+                    freed = true;
+                }
+            }
+        }
+
+        freed
+    }
+
+    #[must_use]
+    pub const fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total_frames,
+            free: self.free_frames,
+            used: self.total_frames - self.free_frames,
+        }
+    }
+
+    /// Put an allocated block back into the free lists, merging it with its free buddies.
+    fn release(&mut self, mut index: usize, mut order: usize) {
+        self.frames[index..index + (1 << order)].fill(FrameState::Free);
+        self.free_frames += 1 << order;
+
+        while order < MAX_ORDER {
+            let buddy = index ^ (1 << order);
+
+            #[expect(clippy::cast_possible_truncation)]
+            if self.frames.get(buddy) != Some(&FrameState::FreeHead(order as u8)) {
+                break;
+            }
+
+            self.remove_free(buddy, order);
+            self.frames[buddy] = FrameState::Free;
+
+            index = index.min(buddy);
+            order += 1;
+        }
+
+        self.push_free(index, order);
+    }
+
+    fn push_free(&mut self, index: usize, order: usize) {
+        let addr = Self::index_to_frame(index).start_address();
+
+        if let Some(next) = self.free_lists[order] {
+            self.free_block(next).prev = Some(addr);
+        }
+        *self.free_block(addr) = FreeBlock {
+            prev: None,
+            next: self.free_lists[order],
+        };
+
+        self.free_lists[order] = Some(addr);
+        #[expect(clippy::cast_possible_truncation)]
+        {
+            self.frames[index] = FrameState::FreeHead(order as u8);
+        }
+    }
+
+    /// First block in the free list of `order` accepted by `fits`.
+    fn find_free(&mut self, order: usize, fits: impl Fn(usize) -> bool) -> Option<usize> {
+        let mut next = self.free_lists[order];
+
+        while let Some(addr) = next {
+            let index = Self::frame_to_index(PhysFrame::containing_address(addr));
+            if fits(index) {
+                return Some(index);
+            }
+
+            next = self.free_block(addr).next;
+        }
+
+        None
+    }
+
+    fn remove_free(&mut self, index: usize, order: usize) {
+        let addr = Self::index_to_frame(index).start_address();
+        let (prev, next) = {
+            let block = self.free_block(addr);
+            (block.prev, block.next)
+        };
+
+        match prev {
+            Some(prev) => self.free_block(prev).next = next,
+            None => self.free_lists[order] = next,
+        }
+        if let Some(next) = next {
+            self.free_block(next).prev = prev;
+        }
+
+        self.frames[index] = FrameState::Free;
+    }
+
+    /// Access the free list node stored at the start of a free frame.
+    #[expect(clippy::needless_pass_by_ref_mut)]
+    fn free_block(&mut self, addr: PhysAddr) -> &mut FreeBlock {
+        let ptr = (self.physical_memory_offset + addr.as_u64()).as_mut_ptr::<FreeBlock>();
+
+        #[expect(unsafe_code)]
+        // SAFETY: Only called for free frames owned by the allocator, that are mapped at the offset.
+        unsafe {
+            &mut *ptr
+        }
+    }
+
+    /// Indices of the 4 KiB frames making up `frame`.
+    const fn frame_indices<S: PageSize>(frame: PhysFrame<S>) -> Range<usize> {
+        let start = Self::frame_to_index(PhysFrame::containing_address(frame.start_address()));
+
+        start..start + (1 << order_of::<S>())
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    #[expect(clippy::integer_division)]
+    const fn frame_to_index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / PAGE_SIZE as u64) as usize
+    }
+
+    const fn index_to_frame(index: usize) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new((index * PAGE_SIZE) as u64))
+    }
+}
+
+/// Order of the blocks backing frames of the page size `S`.
+const fn order_of<S: PageSize>() -> usize {
+    (S::SIZE.trailing_zeros() - PAGE_SIZE.trailing_zeros()) as usize
+}
+
+#[expect(unsafe_code)]
+// SAFETY: A block is only handed out once until it's deallocated again.
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate(0)
+    }
+}
+
+#[expect(unsafe_code)]
+// SAFETY: A block is only handed out once until it's deallocated again.
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        self.allocate_sized()
+    }
+}
+
+#[expect(unsafe_code)]
+// SAFETY: A block is only handed out once until it's deallocated again.
+unsafe impl FrameAllocator<Size1GiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size1GiB>> {
+        self.allocate_sized()
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for BuddyFrameAllocator {
+    #[expect(unsafe_code)]
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        #[expect(unsafe_code)]
+        // SAFETY: The caller guarantees that the frame is unused.
+        unsafe {
+            self.deallocate(
+                PhysFrame::containing_address(frame.start_address()),
+                order_of::<S>(),
+            );
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use spin::Mutex;
+use x86_64::{
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            mapper::MapToError, FrameAllocator, FrameDeallocator, Page, PageTableFlags, Size4KiB,
+        },
+    },
+    VirtAddr,
+};
+
+use super::{
+    get_memory_frame_allocator, get_memory_mapper, map_page, release_region, reserve_region, swap,
+    translate_addr, unmap_page, zero_frame, PAGE_SIZE,
+};
+
+/// Maximum number of lazy regions that can exist at once.
+const MAX_LAZY_REGIONS: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    start: VirtAddr,
+    end: VirtAddr,
+    flags: PageTableFlags,
+}
+
+/// Regions whose pages are mapped on first touch by the page fault handler.
+///
+/// It's a fixed table, since the page fault handler must not allocate.
+static LAZY_REGIONS: Mutex<[Option<Entry>; MAX_LAZY_REGIONS]> =
+    Mutex::new([None; MAX_LAZY_REGIONS]);
+
+/// A reserved kernel virtual range whose pages are backed by zeroed frames on first touch.
+///
+/// The mapped pages are unmapped and the range released when it's dropped.
+#[derive(Debug)]
+pub struct LazyRegion {
+    start: VirtAddr,
+    size: u64,
+}
+
+impl LazyRegion {
+    /// Reserve a lazy region of `size` bytes (rounded up to whole pages), mapped with `flags`.
+    /// Lazy regions hold data, so they're never executable.
+    ///
+    /// Returns `None` when there is no virtual range or lazy region slot left.
+    #[must_use]
+    pub fn new(size: usize, flags: PageTableFlags) -> Option<Self> {
+        let size = (size.max(1) as u64).next_multiple_of(PAGE_SIZE as u64);
+        let start = reserve_region(size, PAGE_SIZE as u64)?;
+
+        let mut regions = LAZY_REGIONS.lock();
+        let Some(slot) = regions.iter_mut().find(|entry| entry.is_none()) else {
+            drop(regions);
+
+            #[expect(unsafe_code)]
+            // SAFETY: Nothing was mapped in the range.
+            unsafe {
+                release_region(start, size);
+            }
+
+            return None;
+        };
+
+        *slot = Some(Entry {
+            start,
+            end: start + size,
+            flags: flags | PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE,
+        });
+
+        Some(Self { start, size })
+    }
+
+    #[must_use]
+    pub const fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    #[must_use]
+    pub const fn as_mut_ptr<T>(&self) -> *mut T {
+        self.start.as_mut_ptr()
+    }
+
+    #[must_use]
+    #[expect(clippy::cast_possible_truncation)]
+    pub const fn len(&self) -> usize {
+        self.size as usize
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+impl Drop for LazyRegion {
+    fn drop(&mut self) {
+        // Unregister first, so no page is mapped again while unmapping
+        if let Some(slot) = LAZY_REGIONS
+            .lock()
+            .iter_mut()
+            .find(|entry| entry.is_some_and(|entry| entry.start == self.start))
+        {
+            *slot = None;
+        }
+
+        let pages = Page::<Size4KiB>::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.start + self.size),
+        );
+        for page in pages {
+            if translate_addr(page.start_address()).is_some() {
+                unmap_page(page).expect("Failed to unmap a page of a lazy region");
+            }
+        }
+        swap::discard(self.start, self.start + self.size);
+
+        #[expect(unsafe_code)]
+        // SAFETY: Nothing is mapped in the range anymore.
+        unsafe {
+            release_region(self.start, self.size);
+        }
+    }
+}
+
+/// Bounds of the live lazy regions.
+pub(super) fn regions() -> [Option<(VirtAddr, VirtAddr)>; MAX_LAZY_REGIONS] {
+    LAZY_REGIONS
+        .lock()
+        .map(|entry| entry.map(|entry| (entry.start, entry.end)))
+}
+
+/// Whether `start..end` overlaps a lazy region.
+pub(super) fn overlaps(start: VirtAddr, end: VirtAddr) -> bool {
+    regions()
+        .iter()
+        .flatten()
+        .any(|&(region_start, region_end)| start < region_end && region_start < end)
+}
+
+/// Map the page containing `addr` when it's part of a lazy region, reading it back
+/// when it was swapped out.
+///
+/// Returns `false` when the fault can't be resolved: the address is outside of lazy regions,
+/// the access isn't allowed by the region's flags, the faulting code holds a lock needed
+/// to map the page, or there is no free frame left.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    debug_assert!(
+        !swap::in_swap_io(),
+        "Page fault at {addr:?} while the swap device was in use"
+    );
+
+    // only faults on missing pages can be resolved
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+
+    // The handler runs with interrupts disabled on the only CPU, so a lock held now was
+    // taken by the code that faulted, and waiting for it would deadlock. Nothing else runs
+    // until the handler returns, so the locks can't be taken in the meantime.
+    if LAZY_REGIONS.is_locked()
+        || swap::is_locked()
+        || get_memory_frame_allocator().is_locked()
+        || get_memory_mapper().is_locked()
+    {
+        return false;
+    }
+
+    let Some(entry) = LAZY_REGIONS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|entry| (entry.start..entry.end).contains(&addr))
+        .copied()
+    else {
+        return false;
+    };
+
+    if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && !entry.flags.contains(PageTableFlags::WRITABLE)
+    {
+        return false;
+    }
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    if let Some(swapped_in) = swap::swap_in(page, entry.flags) {
+        return swapped_in;
+    }
+
+    // out of frames => make room by swapping a cold page out
+    let allocate = || get_memory_frame_allocator().lock().allocate_frame();
+    let Some(frame) = allocate().or_else(|| {
+        swap::swap_out(1);
+        allocate()
+    }) else {
+        return false;
+    };
+
+    #[expect(unsafe_code)]
+    // SAFETY: The frame was just allocated and isn't mapped.
+    unsafe {
+        zero_frame(frame);
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: The page is part of a reserved lazy region and not mapped yet.
+    let Err(err) = (unsafe { map_page(page, frame, entry.flags) }) else {
+        return true;
+    };
+
+    #[expect(unsafe_code)]
+    // SAFETY: The frame wasn't mapped.
+    unsafe {
+        get_memory_frame_allocator().lock().deallocate_frame(frame);
+    }
+
+    // mapped in the meantime
+    matches!(err, MapToError::PageAlreadyMapped(_))
+}
@@ -0,0 +1,147 @@
+use core::ops::Range;
+
+use x86_64::VirtAddr;
+
+use super::PAGE_SIZE;
+use crate::dbg_println;
+
+/// Memory is tested in chunks of this many bytes, so faulty frames are tracked without
+/// allocating.
+const CHUNK_SIZE: u64 = 2 * 1024 * 1024; // 2 MiB
+const CHUNK_FRAMES: usize = 512;
+
+/// Test the frames of `range` (page aligned physical addresses) with walking ones,
+/// address-in-address and moving inversions patterns, reporting faulty ranges to serial.
+///
+/// `on_bad` is called with the address of each faulty frame, in increasing order.
+/// The contents of the frames are overwritten.
+pub fn test_range(
+    physical_memory_offset: VirtAddr,
+    range: Range<u64>,
+    mut on_bad: impl FnMut(u64),
+) {
+    dbg_println!("memtest: testing {:#x}..{:#x}", range.start, range.end);
+
+    let mut faulty: Option<Range<u64>> = None;
+    let mut chunk_start = range.start;
+
+    while chunk_start < range.end {
+        let chunk_end = (chunk_start + CHUNK_SIZE).min(range.end);
+
+        #[expect(unsafe_code)]
+        // SAFETY: The frames are unused and mapped at the physical memory offset.
+        let words = unsafe {
+            core::slice::from_raw_parts_mut(
+                (physical_memory_offset + chunk_start).as_mut_ptr::<u64>(),
+                ((chunk_end - chunk_start) >> 3_u32) as usize,
+            )
+        };
+
+        let mut bad_frames = [false; CHUNK_FRAMES];
+        let mut fail =
+            |index: usize| bad_frames[(index << 3_u32) >> PAGE_SIZE.trailing_zeros()] = true;
+
+        walking_ones(words, &mut fail);
+        address_in_address(words, chunk_start, &mut fail);
+        moving_inversions(words, &mut fail);
+
+        for (index, _) in bad_frames.iter().enumerate().filter(|&(_, &bad)| bad) {
+            let frame = chunk_start + (index * PAGE_SIZE) as u64;
+
+            match faulty {
+                Some(ref mut faulty) if faulty.end == frame => faulty.end += PAGE_SIZE as u64,
+                _ => {
+                    report(faulty.take());
+                    faulty = Some(frame..frame + PAGE_SIZE as u64);
+                }
+            }
+
+            on_bad(frame);
+        }
+
+        chunk_start = chunk_end;
+    }
+
+    report(faulty);
+}
+
+fn report(faulty: Option<Range<u64>>) {
+    if let Some(faulty) = faulty {
+        dbg_println!(
+            "memtest: faulty memory at {:#x}..{:#x}, excluded",
+            faulty.start,
+            faulty.end
+        );
+    }
+}
+
+/// Every word holds a single set bit (then a single cleared bit), moving along the words.
+fn walking_ones(words: &mut [u64], fail: &mut impl FnMut(usize)) {
+    for invert in [0, u64::MAX] {
+        fill_and_check(words, |index| (1_u64 << (index & 63)) ^ invert, fail);
+    }
+}
+
+/// Every word holds its own address (then its complement), to find addressing faults.
+fn address_in_address(words: &mut [u64], base: u64, fail: &mut impl FnMut(usize)) {
+    for invert in [0, u64::MAX] {
+        fill_and_check(
+            words,
+            |index| (base + (index << 3_u32) as u64) ^ invert,
+            fail,
+        );
+    }
+}
+
+/// Fill the words with a pattern, then check and invert them in ascending and descending
+/// order, to find faults caused by writes to neighbouring words.
+fn moving_inversions(words: &mut [u64], fail: &mut impl FnMut(usize)) {
+    for pattern in [0, 0x5555_5555_5555_5555] {
+        for word in words.iter_mut() {
+            write(word, pattern);
+        }
+
+        for (index, word) in words.iter_mut().enumerate() {
+            if read(word) != pattern {
+                fail(index);
+            }
+            write(word, !pattern);
+        }
+
+        for (index, word) in words.iter_mut().enumerate().rev() {
+            if read(word) != !pattern {
+                fail(index);
+            }
+            write(word, pattern);
+        }
+    }
+}
+
+fn fill_and_check(words: &mut [u64], pattern: impl Fn(usize) -> u64, fail: &mut impl FnMut(usize)) {
+    for (index, word) in words.iter_mut().enumerate() {
+        write(word, pattern(index));
+    }
+
+    for (index, word) in words.iter().enumerate() {
+        if read(word) != pattern(index) {
+            fail(index);
+        }
+    }
+}
+
+/// Memory accesses must not be optimized away, or the tests wouldn't touch memory.
+fn write(word: &mut u64, value: u64) {
+    #[expect(unsafe_code)]
+    // SAFETY: The pointer comes from a reference.
+    unsafe {
+        core::ptr::write_volatile(word, value);
+    }
+}
+
+fn read(word: *const u64) -> u64 {
+    #[expect(unsafe_code)]
+    // SAFETY: Only called with pointers from references.
+    unsafe {
+        core::ptr::read_volatile(word)
+    }
+}
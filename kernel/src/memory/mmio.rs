@@ -0,0 +1,211 @@
+use core::mem::ManuallyDrop;
+
+use x86_64::{
+    instructions::tlb,
+    structures::paging::{mapper::MapToError, PageTableFlags, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::{
+    largest_page_size, map_range, pat, release_region, reserve_region, translate_addr, unmap_range,
+    update_range_flags, PAGE_SIZE, PHYSICAL_MEMORY_OFFSET,
+};
+
+/// Memory type used to access an MMIO range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Normal cached memory, e.g. for firmware tables living in RAM.
+    WriteBack,
+    /// Reads are cached, writes go straight to the device.
+    WriteThrough,
+    /// Every access goes to the device, required for device registers.
+    Uncached,
+    /// Writes are buffered and sent to the device in bursts, for framebuffers.
+    WriteCombining,
+}
+
+impl CachePolicy {
+    /// Flags selecting the memory type, in the PAT programmed by `pat::init`.
+    const fn flags(self) -> PageTableFlags {
+        match self {
+            Self::WriteBack => pat::WRITE_BACK_FLAGS,
+            Self::WriteThrough => pat::WRITE_THROUGH_FLAGS,
+            Self::Uncached => pat::UNCACHED_FLAGS,
+            Self::WriteCombining => pat::WRITE_COMBINING_FLAGS,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MmioError {
+    /// No kernel virtual range is large enough for the mapping.
+    OutOfVirtualSpace,
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for MmioError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        Self::Map(err)
+    }
+}
+
+/// A physical range mapped in its own kernel virtual range, unmapped when dropped.
+#[derive(Debug)]
+pub struct MmioMapping {
+    virt: VirtAddr,
+    len: usize,
+}
+
+impl MmioMapping {
+    /// Virtual address of the first byte of the mapped physical range.
+    #[must_use]
+    pub const fn virt_addr(&self) -> VirtAddr {
+        self.virt
+    }
+
+    #[must_use]
+    pub const fn as_mut_ptr<T>(&self) -> *mut T {
+        self.virt.as_mut_ptr()
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Keep the range mapped for the rest of the kernel's lifetime.
+    #[must_use]
+    pub fn leak(self) -> VirtAddr {
+        ManuallyDrop::new(self).virt
+    }
+}
+
+impl Drop for MmioMapping {
+    fn drop(&mut self) {
+        #[expect(unsafe_code)]
+        // SAFETY: The range was mapped by `map_mmio` and is no longer accessed.
+        unsafe {
+            unmap_mmio(self.virt, self.len);
+        }
+    }
+}
+
+/// Map the physical range `phys..phys + len` in an unused kernel virtual range.
+///
+/// Huge pages are used when the range is large and aligned enough.
+///
+/// # Errors
+///
+/// When there is no virtual range left for the mapping, or the page tables can't be allocated.
+pub fn map_mmio(
+    phys: PhysAddr,
+    len: usize,
+    cache_policy: CachePolicy,
+) -> Result<MmioMapping, MmioError> {
+    let phys_start = phys.align_down(PAGE_SIZE as u64);
+    let size = (phys + len.max(1) as u64).align_up(PAGE_SIZE as u64) - phys_start;
+
+    // align the virtual range like the physical one, so it can use the same huge pages
+    let align = largest_page_size(phys_start.as_u64(), size);
+    let virt_start = reserve_region(size, align).ok_or(MmioError::OutOfVirtualSpace)?;
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | cache_policy.flags();
+
+    #[expect(unsafe_code)]
+    // SAFETY: The virtual range was just reserved.
+    if let Err(err) = unsafe { map_range(virt_start, phys_start, size, flags) } {
+        #[expect(unsafe_code)]
+        // SAFETY: Nothing is mapped in the range anymore.
+        unsafe {
+            release_region(virt_start, size);
+        }
+
+        return Err(err.into());
+    }
+
+    Ok(MmioMapping {
+        virt: virt_start + (phys - phys_start),
+        len,
+    })
+}
+
+/// Unmap a range mapped by `map_mmio` and leaked.
+///
+/// # Safety
+///
+/// `virt` and `len` must be those of a leaked `MmioMapping`, and the range must not
+/// be accessed anymore.
+#[expect(unsafe_code)]
+pub unsafe fn unmap_mmio(virt: VirtAddr, len: usize) {
+    let virt_start = virt.align_down(PAGE_SIZE as u64);
+    let size = (virt + len.max(1) as u64).align_up(PAGE_SIZE as u64) - virt_start;
+
+    unmap_range(virt_start, size).expect("Failed to unmap an MMIO range");
+
+    #[expect(unsafe_code)]
+    // SAFETY: Nothing is mapped in the range anymore.
+    unsafe {
+        release_region(virt_start, size);
+    }
+}
+
+/// Change the memory type of the pages mapped in `virt..virt + len` in place, along
+/// with their alias in the physical memory mapping, since mapping the same memory with
+/// different types is undefined.
+///
+/// # Errors
+///
+/// When a page table can't be allocated to split a huge page covering the range.
+///
+/// # Safety
+///
+/// The range must not have any other mapping, and must support the memory type.
+#[expect(unsafe_code)]
+pub unsafe fn set_cache_policy(
+    virt: VirtAddr,
+    len: usize,
+    cache_policy: CachePolicy,
+) -> Result<(), MapToError<Size4KiB>> {
+    let physical_memory_offset = *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("Memory Offset wasn't initialized yet");
+    let update = |flags: PageTableFlags| (flags - pat::MEMORY_TYPE_FLAGS) | cache_policy.flags();
+
+    let virt_start = virt.align_down(PAGE_SIZE as u64);
+    let size = (virt + len.max(1) as u64).align_up(PAGE_SIZE as u64) - virt_start;
+
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        let Some(phys) = translate_addr(virt_start + offset) else {
+            continue;
+        };
+
+        #[expect(unsafe_code)]
+        // SAFETY: Guaranteed by the caller.
+        unsafe {
+            update_range_flags(
+                physical_memory_offset + phys.as_u64(),
+                PAGE_SIZE as u64,
+                update,
+            )?;
+        }
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: Guaranteed by the caller.
+    unsafe {
+        update_range_flags(virt_start, size, update)?;
+    }
+
+    tlb::flush_all();
+    pat::write_back_caches();
+
+    Ok(())
+}
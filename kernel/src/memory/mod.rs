@@ -0,0 +1,637 @@
+mod address_space;
+pub mod dma;
+mod frame_allocator;
+mod lazy;
+#[cfg(feature = "memtest")]
+mod memtest;
+mod mmio;
+mod pat;
+mod protection;
+mod stack;
+mod swap;
+mod virtual_region;
+
+use bootloader_api::info::MemoryRegions;
+use spin::{once::Once, Mutex};
+use x86_64::{
+    instructions::tlb,
+    structures::paging::{
+        mapper::{
+            FlagUpdateError, MapToError, MappedFrame, MapperFlush, TranslateResult, UnmapError,
+        },
+        page_table::PageTableEntry,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+pub use address_space::{copy_from_user, copy_to_user, AddressSpace, AddressSpaceError};
+pub use frame_allocator::{BuddyFrameAllocator, FrameStats};
+pub use lazy::{handle_page_fault, LazyRegion};
+pub use mmio::{map_mmio, set_cache_policy, unmap_mmio, CachePolicy, MmioError, MmioMapping};
+pub use protection::protect_kernel;
+pub use stack::{overflowed_stack, Stack};
+pub use swap::{enable_swap, in_swap_io, swap_out, SwapError};
+pub use virtual_region::RegionAllocator;
+
+pub const PAGE_SIZE: usize = 4096;
+
+static PHYSICAL_MEMORY_OFFSET: Once<VirtAddr> = Once::new();
+/// End of the physical memory mapping, which covers every memory region and the
+/// first 4 GiB, where MMIO lives. Parts of it may be unmapped.
+static PHYSICAL_MEMORY_END: Once<u64> = Once::new();
+static MEMORY_MAPPER: Once<Mutex<OffsetPageTable>> = Once::new();
+static MEMORY_FRAME_ALLOCATOR: Once<Mutex<BuddyFrameAllocator>> = Once::new();
+/// Unused ranges of the kernel (higher half) virtual address space.
+static KERNEL_REGIONS: Once<Mutex<RegionAllocator>> = Once::new();
+
+/// Virtual memory covered by a single level 4 page table entry.
+const LEVEL_4_ENTRY_SIZE: u64 = Size1GiB::SIZE * 512;
+
+/// Initialize a new ``OffsetPageTable``.
+///
+/// # Safety
+///
+/// The caller must guarantee that the complete physical memory is mapped to
+/// virtual memory at the passed `physical_memory_offset`. Also, this function
+/// must be only called once to avoid aliasing `&mut` references
+/// (which is undefined behavior).
+#[expect(unsafe_code)]
+pub unsafe fn init(physical_memory_offset: VirtAddr, memory_regions: &'static MemoryRegions) {
+    PHYSICAL_MEMORY_OFFSET.call_once(|| physical_memory_offset);
+    PHYSICAL_MEMORY_END.call_once(|| {
+        memory_regions
+            .iter()
+            .map(|region| region.end)
+            .fold(1 << 32_u32, u64::max)
+    });
+
+    #[expect(unsafe_code)]
+    // SAFETY: Nothing is mapped with `NO_EXECUTE` by the kernel yet.
+    unsafe {
+        protection::enable_no_execute();
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: Nothing is mapped as write-combining yet, and interrupts are disabled.
+    unsafe {
+        pat::init();
+    }
+
+    // Initialize physical memory mapper
+    MEMORY_MAPPER.call_once(|| {
+        #[expect(clippy::multiple_unsafe_ops_per_block)]
+        #[expect(unsafe_code)]
+        // SAFETY: Offset is correct and level_4_table pointing to valid page table hierarchy.
+        Mutex::new(unsafe {
+            OffsetPageTable::new(
+                active_level_4_table(physical_memory_offset),
+                physical_memory_offset,
+            )
+        })
+    });
+
+    // Initialize memory frame allocator
+    #[expect(unsafe_code)]
+    MEMORY_FRAME_ALLOCATOR.call_once(|| {
+        // SAFETY: Memory regions are valid, all of their usable frames are unused,
+        // and the physical memory is mapped at the offset.
+        Mutex::new(unsafe { BuddyFrameAllocator::init(memory_regions, physical_memory_offset) })
+    });
+
+    // Every unused level 4 entry of the higher half is free for the kernel to reserve.
+    // The last one is skipped, so region ends never overflow.
+    KERNEL_REGIONS.call_once(|| {
+        let mut regions = RegionAllocator::new();
+        let mapper = get_memory_mapper().lock();
+
+        for (index, entry) in mapper
+            .level_4_table()
+            .iter()
+            .enumerate()
+            .take(511)
+            .skip(256)
+        {
+            if entry.is_unused() {
+                let start = VirtAddr::new_truncate(index as u64 * LEVEL_4_ENTRY_SIZE);
+
+                #[expect(clippy::cast_possible_truncation)]
+                regions.deallocate(start.as_u64() as usize, LEVEL_4_ENTRY_SIZE as usize);
+            }
+        }
+
+        Mutex::new(regions)
+    });
+
+    // Uses the unused higher half entries, so it must come after collecting them
+    address_space::init(
+        &mut get_memory_mapper().lock(),
+        &mut get_memory_frame_allocator().lock(),
+    );
+}
+
+#[expect(unsafe_code)]
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr
+}
+
+fn get_memory_mapper() -> &'static Mutex<OffsetPageTable<'static>> {
+    MEMORY_MAPPER
+        .get()
+        .expect("Memory Mapper wasn't initialized yet")
+}
+
+/// Physical address `addr` is mapped to, if it's mapped.
+pub fn translate_addr(addr: VirtAddr) -> Option<PhysAddr> {
+    get_memory_mapper().lock().translate_addr(addr)
+}
+
+/// Reserve an unused kernel virtual range of `size` bytes, aligned to `align` (a power of 2).
+///
+/// Nothing is mapped in the range, it's up to the caller.
+#[expect(clippy::cast_possible_truncation)]
+pub fn reserve_region(size: u64, align: u64) -> Option<VirtAddr> {
+    let start = KERNEL_REGIONS
+        .get()
+        .expect("Kernel regions weren't initialized yet")
+        .lock()
+        .allocate(size as usize, align as usize)?;
+
+    Some(VirtAddr::new(start as u64))
+}
+
+/// Give back a range reserved with `reserve_region`.
+///
+/// # Safety
+///
+/// Nothing must be mapped or used in the range anymore.
+#[expect(unsafe_code)]
+#[expect(clippy::cast_possible_truncation)]
+pub unsafe fn release_region(start: VirtAddr, size: u64) {
+    KERNEL_REGIONS
+        .get()
+        .expect("Kernel regions weren't initialized yet")
+        .lock()
+        .deallocate(start.as_u64() as usize, size as usize);
+}
+
+pub fn get_memory_frame_allocator() -> &'static Mutex<BuddyFrameAllocator> {
+    MEMORY_FRAME_ALLOCATOR
+        .get()
+        .expect("Memory Frame Allocator wasn't initialized yet")
+}
+
+#[expect(unsafe_code)]
+/// Map `page` to `frame`.
+///
+/// Frames handed out by the frame allocator are reference counted by their mappings,
+/// so the mapping takes ownership of `frame` and `unmap_page` returns it to the allocator
+/// once its last mapping is removed. Other frames (MMIO, firmware) are never freed.
+///
+/// # Safety
+///
+/// `virtual_address` must not interfere with the heap or being mapped to other frame,
+/// and `physical_address` must not be already mapped to other virtual address
+/// (otherwise it will be two `&mut` references).
+pub unsafe fn map_page<S: PageSize>(
+    page: Page<S>,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<S>>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    let mut frame_allocator = get_memory_frame_allocator().lock();
+
+    #[expect(unsafe_code)]
+    // SAFETY: Mapping valid and unused memory.
+    unsafe {
+        get_memory_mapper()
+            .lock()
+            .map_to(page, frame, flags, &mut *frame_allocator)?
+            .flush();
+    }
+
+    frame_allocator.add_mapping(frame);
+
+    Ok(())
+}
+
+/// Unmap `page`, returning its frame to the frame allocator when it was its last mapping.
+pub fn unmap_page<S: PageSize>(page: Page<S>) -> Result<(), UnmapError>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    let (frame, flush) = get_memory_mapper().lock().unmap(page)?;
+    flush.flush();
+
+    get_memory_frame_allocator().lock().remove_mapping(frame);
+
+    Ok(())
+}
+
+/// Map the physically contiguous range `phys..phys + size` at `virt`, using 2 MiB and
+/// 1 GiB pages wherever the alignment of both addresses allows it.
+///
+/// On failure, the pages mapped so far are unmapped again.
+///
+/// # Safety
+///
+/// Same as `map_page`, for every page of the range.
+#[expect(unsafe_code)]
+pub unsafe fn map_range(
+    virt: VirtAddr,
+    phys: PhysAddr,
+    size: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut offset = 0;
+
+    while offset < size {
+        let page_size = largest_page_size(
+            (virt + offset).as_u64() | (phys + offset).as_u64(),
+            size - offset,
+        );
+
+        let map: unsafe fn(VirtAddr, PhysAddr, PageTableFlags) -> _ = match page_size {
+            Size1GiB::SIZE => map_frame::<Size1GiB>,
+            Size2MiB::SIZE => map_frame::<Size2MiB>,
+            _ => map_frame::<Size4KiB>,
+        };
+
+        #[expect(unsafe_code)]
+        // SAFETY: Guaranteed by the caller.
+        if let Err(err) = unsafe { map(virt + offset, phys + offset, flags) } {
+            unmap_range(virt, offset).expect("Failed to unmap a partially mapped range");
+
+            return Err(err);
+        }
+
+        offset += page_size;
+    }
+
+    Ok(())
+}
+
+/// Map the page of size `S` at `virt` to the frame at `phys`.
+///
+/// # Safety
+///
+/// Same as `map_page`.
+#[expect(unsafe_code)]
+unsafe fn map_frame<S: PageSize>(
+    virt: VirtAddr,
+    phys: PhysAddr,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    #[expect(unsafe_code)]
+    // SAFETY: Guaranteed by the caller.
+    unsafe {
+        map_page(
+            Page::<S>::containing_address(virt),
+            PhysFrame::containing_address(phys),
+            flags,
+        )
+    }
+    .map_err(|err| into_4kib_error(&err))
+}
+
+/// Map `virt..virt + size` to newly allocated frames, using 2 MiB and 1 GiB frames
+/// wherever the alignment allows it and the frame allocator has them.
+///
+/// On failure, the pages mapped so far are unmapped again and their frames freed.
+///
+/// # Safety
+///
+/// The virtual range must be unused.
+#[expect(unsafe_code)]
+pub unsafe fn map_anonymous(
+    virt: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut offset = 0;
+
+    while offset < size {
+        let page_addr = virt + offset;
+        let mut page_size = largest_page_size(page_addr.as_u64(), size - offset);
+
+        let result = loop {
+            let map: unsafe fn(VirtAddr, PageTableFlags) -> _ = match page_size {
+                Size1GiB::SIZE => map_new_frame::<Size1GiB>,
+                Size2MiB::SIZE => map_new_frame::<Size2MiB>,
+                _ => map_new_frame::<Size4KiB>,
+            };
+
+            #[expect(unsafe_code)]
+            // SAFETY: The virtual range is unused.
+            match unsafe { map(page_addr, flags) } {
+                Some(result) => break result,
+                None if page_size == Size4KiB::SIZE => {
+                    break Err(MapToError::FrameAllocationFailed)
+                }
+                // no free frame of this size => fall back to smaller ones
+                None if page_size == Size1GiB::SIZE => page_size = Size2MiB::SIZE,
+                None => page_size = Size4KiB::SIZE,
+            }
+        };
+
+        if let Err(err) = result {
+            unmap_range(virt, offset).expect("Failed to unmap a partially mapped range");
+
+            return Err(err);
+        }
+
+        offset += page_size;
+    }
+
+    Ok(())
+}
+
+/// Map the page of size `S` at `addr` to a newly allocated frame.
+///
+/// Returns `None` when there is no free frame of that size.
+///
+/// # Safety
+///
+/// The page must be unused.
+#[expect(unsafe_code)]
+unsafe fn map_new_frame<S: PageSize>(
+    addr: VirtAddr,
+    flags: PageTableFlags,
+) -> Option<Result<(), MapToError<Size4KiB>>>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    let frame = get_memory_frame_allocator().lock().allocate_sized::<S>()?;
+
+    #[expect(unsafe_code)]
+    // SAFETY: The page is unused and the frame was just allocated.
+    let result = unsafe { map_page(Page::containing_address(addr), frame, flags) };
+
+    Some(result.map_err(|err| {
+        #[expect(unsafe_code)]
+        // SAFETY: The frame was just allocated and isn't mapped.
+        unsafe {
+            get_memory_frame_allocator().lock().deallocate_frame(frame);
+        }
+
+        into_4kib_error(&err)
+    }))
+}
+
+/// Fill `frame` with zeros through the physical memory mapping.
+///
+/// # Safety
+///
+/// The frame must be unused.
+#[expect(unsafe_code)]
+unsafe fn zero_frame(frame: PhysFrame) {
+    let virt = *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("Memory Offset wasn't initialized yet")
+        + frame.start_address().as_u64();
+
+    #[expect(unsafe_code)]
+    // SAFETY: The frame is unused and mapped at the physical memory offset.
+    unsafe {
+        core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, PAGE_SIZE);
+    }
+}
+
+/// The page table in `frame`, through the physical memory mapping.
+///
+/// # Safety
+///
+/// `frame` must hold a page table that isn't referenced anywhere else.
+#[expect(unsafe_code)]
+unsafe fn page_table<'table>(frame: PhysFrame) -> &'table mut PageTable {
+    let virt = *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("Memory Offset wasn't initialized yet")
+        + frame.start_address().as_u64();
+
+    #[expect(unsafe_code)]
+    // SAFETY: Guaranteed by the caller.
+    unsafe {
+        &mut *virt.as_mut_ptr::<PageTable>()
+    }
+}
+
+/// Unmap every page in `virt..virt + size`, whatever its size, returning the frames
+/// to the frame allocator when it was their last mapping.
+///
+/// The range must start and end on boundaries of the pages mapping it.
+pub fn unmap_range(virt: VirtAddr, size: u64) -> Result<(), UnmapError> {
+    let mut offset = 0;
+
+    while offset < size {
+        let addr = virt + offset;
+        let translation = get_memory_mapper().lock().translate(addr);
+
+        offset += match translation {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size1GiB(_),
+                ..
+            } => {
+                unmap_page(Page::<Size1GiB>::containing_address(addr))?;
+                Size1GiB::SIZE
+            }
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size2MiB(_),
+                ..
+            } => {
+                unmap_page(Page::<Size2MiB>::containing_address(addr))?;
+                Size2MiB::SIZE
+            }
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(_),
+                ..
+            } => {
+                unmap_page(Page::<Size4KiB>::containing_address(addr))?;
+                Size4KiB::SIZE
+            }
+            TranslateResult::NotMapped => return Err(UnmapError::PageNotMapped),
+            TranslateResult::InvalidFrameAddress(addr) => {
+                return Err(UnmapError::InvalidFrameAddress(addr))
+            }
+        };
+    }
+
+    Ok(())
+}
+
+/// Change the flags of every page mapped in `virt..virt + size` to `update(flags)`.
+///
+/// Huge pages crossing the bounds of the range are split first, so the pages outside of
+/// it keep their flags. Unmapped pages are skipped.
+///
+/// # Safety
+///
+/// The new flags must not break the accesses made to the range.
+#[expect(unsafe_code)]
+unsafe fn update_range_flags(
+    virt: VirtAddr,
+    size: u64,
+    update: impl Fn(PageTableFlags) -> PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let end = virt + size;
+    let mut addr = virt;
+
+    while addr < end {
+        let mut frame_allocator = get_memory_frame_allocator().lock();
+        let mut mapper = get_memory_mapper().lock();
+
+        let TranslateResult::Mapped { frame, flags, .. } = mapper.translate(addr) else {
+            addr = addr.align_down(Size4KiB::SIZE) + Size4KiB::SIZE;
+            continue;
+        };
+        let page_start = addr.align_down(frame.size());
+
+        if page_start < virt || end - page_start < frame.size() {
+            split_huge_page(&mut mapper, &mut frame_allocator, addr)?;
+            continue;
+        }
+
+        let update_flags: unsafe fn(&mut OffsetPageTable<'static>, VirtAddr, PageTableFlags) -> _ =
+            match frame {
+                MappedFrame::Size1GiB(_) => update_page_flags::<Size1GiB>,
+                MappedFrame::Size2MiB(_) => update_page_flags::<Size2MiB>,
+                MappedFrame::Size4KiB(_) => update_page_flags::<Size4KiB>,
+            };
+
+        #[expect(unsafe_code)]
+        // SAFETY: Guaranteed by the caller.
+        unsafe { update_flags(&mut mapper, addr, update(flags)) }
+            .expect("The page was just translated");
+
+        addr = page_start + frame.size();
+    }
+
+    Ok(())
+}
+
+/// Set the flags of the page of size `S` at `addr`.
+///
+/// # Safety
+///
+/// Same as `update_range_flags`.
+#[expect(unsafe_code)]
+unsafe fn update_page_flags<S: PageSize>(
+    mapper: &mut OffsetPageTable<'static>,
+    addr: VirtAddr,
+    flags: PageTableFlags,
+) -> Result<(), FlagUpdateError>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    #[expect(unsafe_code)]
+    // SAFETY: Guaranteed by the caller.
+    unsafe { mapper.update_flags(Page::<S>::containing_address(addr), flags) }
+        .map(MapperFlush::flush)
+}
+
+/// Replace the huge page mapping `addr` with a page table mapping the same frames with
+/// the same flags, in pages of the next smaller size.
+fn split_huge_page(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BuddyFrameAllocator,
+    addr: VirtAddr,
+) -> Result<(), MapToError<Size4KiB>> {
+    let next_table = |entry: &PageTableEntry| {
+        #[expect(unsafe_code)]
+        // SAFETY: The entry points to a page table, and the page tables are locked.
+        unsafe {
+            page_table::<'static>(PhysFrame::containing_address(entry.addr()))
+        }
+    };
+
+    let level_3_entry = &mut next_table(&mapper.level_4_table()[addr.p4_index()])[addr.p3_index()];
+
+    let (entry, page_size) = if level_3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        (level_3_entry, Size2MiB::SIZE)
+    } else {
+        (
+            &mut next_table(level_3_entry)[addr.p2_index()],
+            Size4KiB::SIZE,
+        )
+    };
+
+    let mut page_flags = entry.flags();
+    if page_size == Size4KiB::SIZE {
+        // the `HUGE_PAGE` bit selects the PAT in 4 KiB entries, which the kernel never uses
+        page_flags -= PageTableFlags::HUGE_PAGE;
+    }
+
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+    #[expect(unsafe_code)]
+    // SAFETY: The frame was just allocated.
+    let table = unsafe { page_table(frame) };
+    let start = entry.addr();
+    for (index, page) in table.iter_mut().enumerate() {
+        page.set_addr(start + index as u64 * page_size, page_flags);
+    }
+
+    // the pages keep their permissions, the table entry only points to them
+    let table_flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | (entry.flags() & PageTableFlags::USER_ACCESSIBLE);
+    entry.set_frame(frame, table_flags);
+    tlb::flush_all();
+
+    Ok(())
+}
+
+/// Largest page size that `addr` is aligned to and that fits in `remaining` bytes.
+fn largest_page_size(addr: u64, remaining: u64) -> u64 {
+    let fits = |page_size: u64| remaining >= page_size && addr.is_multiple_of(page_size);
+
+    if fits(Size1GiB::SIZE) && supports_1gib_pages() {
+        Size1GiB::SIZE
+    } else if fits(Size2MiB::SIZE) {
+        Size2MiB::SIZE
+    } else {
+        Size4KiB::SIZE
+    }
+}
+
+/// Whether the CPU supports 1 GiB pages (CPUID `Page1GB`).
+fn supports_1gib_pages() -> bool {
+    static SUPPORTED: Once<bool> = Once::new();
+
+    *SUPPORTED.call_once(|| {
+        let extended_features = core::arch::x86_64::__cpuid(0x8000_0001);
+
+        extended_features.edx & (1_u32 << 26_u32) != 0
+    })
+}
+
+/// Convert an error of mapping a huge page to the error of its first 4 KiB page.
+const fn into_4kib_error<S: PageSize>(err: &MapToError<S>) -> MapToError<Size4KiB> {
+    match *err {
+        MapToError::FrameAllocationFailed => MapToError::FrameAllocationFailed,
+        MapToError::ParentEntryHugePage => MapToError::ParentEntryHugePage,
+        MapToError::PageAlreadyMapped(frame) => {
+            MapToError::PageAlreadyMapped(PhysFrame::containing_address(frame.start_address()))
+        }
+    }
+}
+
+pub fn frame_stats() -> FrameStats {
+    get_memory_frame_allocator().lock().stats()
+}
@@ -0,0 +1,64 @@
+use x86_64::{
+    instructions::tlb, registers::model_specific::Msr, structures::paging::PageTableFlags,
+};
+
+const IA32_PAT: u32 = 0x277;
+
+/// Memory types encodings of the PAT entries.
+const WRITE_BACK: u64 = 0x06;
+const WRITE_THROUGH: u64 = 0x04;
+const WRITE_COMBINING: u64 = 0x01;
+const UNCACHED: u64 = 0x00;
+
+/// Memory types of the PAT entries selected by the `PWT` and `PCD` flags.
+///
+/// Only the entry selected by `PCD` alone differs from the power-on default (UC-),
+/// so the mappings created by the bootloader keep their memory types. The entries
+/// selected with the `PAT` flag repeat the first four, as the kernel never sets it.
+const PAT_ENTRIES: [u64; 4] = [WRITE_BACK, WRITE_THROUGH, WRITE_COMBINING, UNCACHED];
+
+/// Page table flags selecting each memory type, following `PAT_ENTRIES`.
+pub const WRITE_BACK_FLAGS: PageTableFlags = PageTableFlags::empty();
+pub const WRITE_THROUGH_FLAGS: PageTableFlags = PageTableFlags::WRITE_THROUGH;
+pub const WRITE_COMBINING_FLAGS: PageTableFlags = PageTableFlags::NO_CACHE;
+pub const UNCACHED_FLAGS: PageTableFlags =
+    PageTableFlags::NO_CACHE.union(PageTableFlags::WRITE_THROUGH);
+
+/// Flags selecting the memory type of a mapping.
+pub const MEMORY_TYPE_FLAGS: PageTableFlags =
+    PageTableFlags::NO_CACHE.union(PageTableFlags::WRITE_THROUGH);
+
+/// Program the `IA32_PAT` MSR with `PAT_ENTRIES`.
+///
+/// # Safety
+///
+/// Must be called before any mapping uses write-combining, with interrupts disabled.
+#[expect(unsafe_code)]
+pub unsafe fn init() {
+    let mut value = 0;
+    for (index, entry) in PAT_ENTRIES.iter().chain(&PAT_ENTRIES).enumerate() {
+        value |= entry << (index * 8);
+    }
+
+    // Cached lines of memory whose type changes must be written back first
+    write_back_caches();
+
+    #[expect(unsafe_code)]
+    // SAFETY: The memory types of existing mappings are unchanged, except for `PCD` alone
+    // which no mapping uses yet.
+    unsafe {
+        Msr::new(IA32_PAT).write(value);
+    }
+
+    tlb::flush_all();
+}
+
+/// Write back and invalidate the caches, so no line stays cached with the memory type
+/// its memory had before being remapped.
+pub fn write_back_caches() {
+    #[expect(unsafe_code)]
+    // SAFETY: Only writes back and invalidates the caches.
+    unsafe {
+        core::arch::asm!("wbinvd", options(nostack, preserves_flags));
+    }
+}
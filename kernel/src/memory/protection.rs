@@ -0,0 +1,233 @@
+use core::arch::x86_64::__cpuid_count;
+
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        mapper::{MappedFrame, TranslateResult},
+        Page, PageTableFlags, Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{
+    get_memory_mapper, update_range_flags, PAGE_SIZE, PHYSICAL_MEMORY_END, PHYSICAL_MEMORY_OFFSET,
+};
+
+/// ELF program header types.
+const PT_LOAD: u32 = 1;
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+/// ELF program header flags.
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+/// A segment of the kernel ELF, with its address in the loaded image.
+#[derive(Clone, Copy)]
+struct Segment {
+    kind: u32,
+    flags: u32,
+    start: VirtAddr,
+    end: VirtAddr,
+}
+
+impl Segment {
+    /// Whether the segment overlaps `page`.
+    fn contains(&self, page: Page) -> bool {
+        page.start_address() < self.end && self.start < page.start_address() + PAGE_SIZE as u64
+    }
+
+    /// Whether `page` is entirely in the segment.
+    fn covers(&self, page: Page) -> bool {
+        self.start <= page.start_address() && page.start_address() + PAGE_SIZE as u64 <= self.end
+    }
+}
+
+/// Enable `NO_EXECUTE` page table flags.
+///
+/// # Safety
+///
+/// Must be called before any mapping uses `NO_EXECUTE`.
+#[expect(unsafe_code)]
+pub unsafe fn enable_no_execute() {
+    #[expect(unsafe_code)]
+    // SAFETY: Only allows a new page table flag.
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    }
+}
+
+/// Remap the kernel image so its code is read-only and its data non-executable, and
+/// enable the CPU protections: write protection of read-only pages in the kernel, and
+/// SMEP/SMAP when the CPU supports them.
+///
+/// The physical memory mapping is made non-executable, and read-only where it aliases
+/// the read-only pages of the kernel image, so it can't be used to bypass them.
+///
+/// `kernel_addr` and `kernel_len` locate the kernel ELF in physical memory, and
+/// `kernel_image_offset` is where it was loaded.
+///
+/// # Safety
+///
+/// The values must be those passed by the bootloader, and the kernel must not write to
+/// its code or read-only data, execute its data, or access user memory other than with
+/// `copy_from_user` and `copy_to_user`.
+#[expect(unsafe_code)]
+pub unsafe fn protect_kernel(kernel_addr: u64, kernel_len: u64, kernel_image_offset: u64) {
+    let physical_memory_offset = *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("Memory Offset wasn't initialized yet");
+    let physical_memory_end = *PHYSICAL_MEMORY_END
+        .get()
+        .expect("Memory Offset wasn't initialized yet");
+    let elf_start = physical_memory_offset + PhysAddr::new(kernel_addr).as_u64();
+
+    #[expect(unsafe_code)]
+    #[expect(clippy::cast_possible_truncation)]
+    // SAFETY: The kernel ELF stays in memory and is mapped at the physical memory offset.
+    let elf = unsafe { core::slice::from_raw_parts(elf_start.as_ptr::<u8>(), kernel_len as usize) };
+
+    let segments = || segments(elf, kernel_image_offset);
+
+    // Nothing is executed through the physical memory mapping
+    #[expect(unsafe_code)]
+    // SAFETY: Guaranteed by the caller.
+    unsafe {
+        update_range_flags(physical_memory_offset, physical_memory_end, |flags| {
+            flags | PageTableFlags::NO_EXECUTE
+        })
+    }
+    .expect("Failed to map the physical memory as non-executable");
+
+    for segment in
+        segments().filter(|segment| segment.kind == PT_LOAD && segment.start < segment.end)
+    {
+        let pages = Page::<Size4KiB>::range_inclusive(
+            Page::containing_address(segment.start),
+            Page::containing_address(segment.end - 1_u64),
+        );
+
+        for page in pages {
+            let TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } = get_memory_mapper().lock().translate(page.start_address())
+            else {
+                continue;
+            };
+
+            // a page shared by several segments gets the permissions of all of them
+            let mut writable = false;
+            let mut executable = false;
+            let mut relro = false;
+            for other in segments() {
+                match other.kind {
+                    PT_LOAD if other.contains(page) => {
+                        writable |= other.flags & PF_W != 0;
+                        executable |= other.flags & PF_X != 0;
+                    }
+                    // only writable while the bootloader relocates the kernel, but its last
+                    // page may be shared with writable data
+                    PT_GNU_RELRO => relro |= other.covers(page),
+                    _ => (),
+                }
+            }
+            let writable = writable && !relro;
+
+            let mut new_flags = flags - PageTableFlags::WRITABLE - PageTableFlags::NO_EXECUTE;
+            if writable {
+                new_flags |= PageTableFlags::WRITABLE;
+            }
+            if !executable {
+                new_flags |= PageTableFlags::NO_EXECUTE;
+            }
+
+            #[expect(unsafe_code)]
+            // SAFETY: The kernel doesn't access its image in ways forbidden by its segments.
+            unsafe { update_range_flags(page.start_address(), PAGE_SIZE as u64, |_| new_flags) }
+                .expect("A 4 KiB page is never split");
+
+            // the alias of read-only pages in the physical memory mapping must not allow
+            // writing to them either
+            if !writable {
+                #[expect(unsafe_code)]
+                // SAFETY: Same as above.
+                unsafe {
+                    update_range_flags(
+                        physical_memory_offset + frame.start_address().as_u64(),
+                        PAGE_SIZE as u64,
+                        |flags| flags - PageTableFlags::WRITABLE,
+                    )
+                }
+                .expect("Failed to map the kernel image read-only in the physical memory mapping");
+            }
+        }
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: Read-only pages aren't written to by the kernel.
+    unsafe {
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+
+    // CPUID.(EAX=7, ECX=0):EBX
+    let features = __cpuid_count(7, 0).ebx;
+    let mut cr4_flags = Cr4Flags::empty();
+    if features & (1_u32 << 7_u32) != 0 {
+        cr4_flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if features & (1_u32 << 20_u32) != 0 {
+        cr4_flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+
+    #[expect(unsafe_code)]
+    // SAFETY: The kernel doesn't execute user memory, and only accesses it with SMAP lifted.
+    unsafe {
+        Cr4::update(|flags| flags.insert(cr4_flags));
+    }
+}
+
+/// Parse the program headers of the ELF `elf`, loaded at `image_offset`.
+fn segments(elf: &[u8], image_offset: u64) -> impl Iterator<Item = Segment> + '_ {
+    let read_u16 = |offset: usize| {
+        elf.get(offset..offset + 2)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map_or(0, u16::from_le_bytes)
+    };
+    let read_u32 = |offset: usize| {
+        elf.get(offset..offset + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map_or(0, u32::from_le_bytes)
+    };
+    let read_u64 = |offset: usize| {
+        elf.get(offset..offset + 8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map_or(0, u64::from_le_bytes)
+    };
+
+    let is_elf = elf.starts_with(b"\x7fELF");
+
+    #[expect(clippy::cast_possible_truncation)]
+    let header_offset = read_u64(0x20) as usize;
+    let header_size = usize::from(read_u16(0x36));
+    let header_count = if is_elf {
+        usize::from(read_u16(0x38))
+    } else {
+        0
+    };
+
+    (0..header_count).map(move |index| {
+        let header = header_offset + index * header_size;
+        let start = VirtAddr::new_truncate(image_offset + read_u64(header + 0x10));
+        let size = read_u64(header + 0x28);
+
+        Segment {
+            kind: read_u32(header),
+            flags: read_u32(header + 0x04),
+            start,
+            end: start + size,
+        }
+    })
+}
@@ -0,0 +1,111 @@
+use spin::Mutex;
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+use super::{map_anonymous, release_region, reserve_region, unmap_range, PAGE_SIZE};
+
+/// Maximum number of stacks that can exist at once.
+const MAX_STACKS: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    /// Start of the unmapped guard page under the stack.
+    guard: VirtAddr,
+    name: &'static str,
+}
+
+/// Guard pages of the live stacks, to tell which stack overflowed on a fault.
+///
+/// It's a fixed table, since the page fault handler must not allocate.
+static STACKS: Mutex<[Option<Entry>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+/// A kernel stack mapped in its own virtual range, with an unmapped guard page under it.
+///
+/// Overflowing the stack faults on the guard page instead of corrupting the memory below.
+#[derive(Debug)]
+pub struct Stack {
+    guard: VirtAddr,
+    size: u64,
+}
+
+impl Stack {
+    /// Map a stack of `size` bytes (rounded up to whole pages), named `name` in fault reports.
+    ///
+    /// Returns `None` when there is no memory, virtual range or stack slot left.
+    #[must_use]
+    pub fn new(size: usize, name: &'static str) -> Option<Self> {
+        let size = (size as u64).next_multiple_of(PAGE_SIZE as u64);
+        let guard = reserve_region(size + PAGE_SIZE as u64, PAGE_SIZE as u64)?;
+
+        let release = || {
+            #[expect(unsafe_code)]
+            // SAFETY: Nothing is mapped in the range.
+            unsafe {
+                release_region(guard, size + PAGE_SIZE as u64);
+            }
+        };
+
+        let mut stacks = STACKS.lock();
+        let Some(slot) = stacks.iter_mut().find(|entry| entry.is_none()) else {
+            release();
+            return None;
+        };
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        #[expect(unsafe_code)]
+        // SAFETY: The virtual range was just reserved.
+        if unsafe { map_anonymous(guard + PAGE_SIZE as u64, size, flags) }.is_err() {
+            release();
+            return None;
+        }
+
+        *slot = Some(Entry { guard, name });
+
+        Some(Self { guard, size })
+    }
+
+    /// Address right above the stack, where it starts growing down from.
+    #[must_use]
+    pub fn top(&self) -> VirtAddr {
+        self.guard + PAGE_SIZE as u64 + self.size
+    }
+
+    /// Keep the stack mapped for the rest of the kernel's lifetime, returning its top.
+    #[must_use]
+    pub fn leak(self) -> VirtAddr {
+        core::mem::ManuallyDrop::new(self).top()
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        if let Some(slot) = STACKS
+            .lock()
+            .iter_mut()
+            .find(|entry| entry.is_some_and(|entry| entry.guard == self.guard))
+        {
+            *slot = None;
+        }
+
+        unmap_range(self.guard + PAGE_SIZE as u64, self.size).expect("Failed to unmap a stack");
+
+        #[expect(unsafe_code)]
+        // SAFETY: Nothing is mapped in the range anymore.
+        unsafe {
+            release_region(self.guard, self.size + PAGE_SIZE as u64);
+        }
+    }
+}
+
+/// Name of the stack whose guard page contains `addr`, if any.
+///
+/// Uses `try_lock`, as it's called from fault handlers that may have interrupted
+/// the creation of a stack.
+pub fn overflowed_stack(addr: VirtAddr) -> Option<&'static str> {
+    STACKS
+        .try_lock()?
+        .iter()
+        .flatten()
+        .find(|entry| (entry.guard..entry.guard + PAGE_SIZE as u64).contains(&addr))
+        .map(|entry| entry.name)
+}
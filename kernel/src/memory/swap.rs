@@ -0,0 +1,430 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    instructions::tlb,
+    structures::paging::{
+        page_table::PageTableEntry, FrameAllocator, FrameDeallocator, Page, PageSize, PageTable,
+        PageTableFlags, PhysFrame, Size1GiB, Size2MiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{
+    get_memory_frame_allocator, get_memory_mapper, lazy, map_page, page_table, LEVEL_4_ENTRY_SIZE,
+    PAGE_SIZE, PHYSICAL_MEMORY_OFFSET,
+};
+use crate::drivers::block::{BlockDevice, BlockError};
+
+/// Marks a non-present level 1 entry whose page is in the swap slot given by its address.
+const SWAPPED: PageTableFlags = PageTableFlags::BIT_9;
+
+static SWAP: Mutex<Option<Swap>> = Mutex::new(None);
+
+/// Set while the swap device reads or writes a page.
+static SWAP_IO: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum SwapError {
+    AlreadyEnabled,
+    /// The area is out of the device, or its block size doesn't divide pages.
+    InvalidArea,
+}
+
+struct Swap {
+    device: Box<dyn BlockDevice>,
+    /// First block of the swap area on the device.
+    start_block: u64,
+    blocks_per_slot: u64,
+    /// One bit for each page-sized slot of the area, set when it holds a page.
+    used_slots: Vec<u64>,
+    slot_count: usize,
+    /// Where the next scan for pages to evict starts.
+    hand: VirtAddr,
+}
+
+/// Swap cold pages of lazy regions out to the `block_count` blocks of `device` starting
+/// at `start_block`, e.g. a swap partition or the blocks of a swap file.
+///
+/// Pages are swapped out when the frame allocator runs out of frames in the page fault
+/// handler, or with `swap_out`. The device is used from the page fault handler, so it
+/// must not touch lazy regions itself, allocate, or fault at all: debug builds check it.
+/// It must not wait for locks the faulting code may hold either.
+///
+/// # Errors
+///
+/// When swap is already enabled or the area is invalid.
+pub fn enable_swap(
+    device: Box<dyn BlockDevice>,
+    start_block: u64,
+    block_count: u64,
+) -> Result<(), SwapError> {
+    let mut swap = SWAP.lock();
+    if swap.is_some() {
+        return Err(SwapError::AlreadyEnabled);
+    }
+
+    let block_size = device.block_size();
+    if !block_size.is_power_of_two()
+        || block_size > PAGE_SIZE
+        || start_block
+            .checked_add(block_count)
+            .is_none_or(|end| end > device.block_count())
+    {
+        return Err(SwapError::InvalidArea);
+    }
+
+    #[expect(clippy::integer_division)]
+    let blocks_per_slot = (PAGE_SIZE / block_size) as u64;
+    #[expect(clippy::integer_division)]
+    #[expect(clippy::cast_possible_truncation)]
+    let slot_count = (block_count / blocks_per_slot) as usize;
+
+    *swap = Some(Swap {
+        device,
+        start_block,
+        blocks_per_slot,
+        used_slots: vec![0; slot_count.div_ceil(64)],
+        slot_count,
+        hand: VirtAddr::zero(),
+    });
+
+    Ok(())
+}
+
+/// Write up to `count` of the least recently used pages of lazy regions to swap,
+/// freeing their frames.
+///
+/// Returns how many pages were swapped out.
+pub fn swap_out(count: usize) -> usize {
+    let mut swap = SWAP.lock();
+    let Some(swap) = swap.as_mut() else {
+        return 0;
+    };
+
+    (0..count).take_while(|_| swap.evict_one()).count()
+}
+
+/// Read `page` back from swap into a new frame mapped with `flags`.
+///
+/// Returns `None` when the page isn't swapped out, and whether it could be mapped
+/// again otherwise.
+pub(super) fn swap_in(page: Page, flags: PageTableFlags) -> Option<bool> {
+    let mut swap = SWAP.lock();
+    let swap = swap.as_mut()?;
+
+    let slot = {
+        let mut mapper = get_memory_mapper().lock();
+
+        #[expect(unsafe_code)]
+        // SAFETY: The entry is only used while the page tables are locked.
+        unsafe { level_1_entry(mapper.level_4_table_mut(), page.start_address()) }
+            .ok()
+            .and_then(|entry| swap_slot(entry))
+    }?;
+
+    let allocate = || get_memory_frame_allocator().lock().allocate_frame();
+    let Some(frame) = allocate().or_else(|| {
+        swap.evict_one();
+        allocate()
+    }) else {
+        return Some(false);
+    };
+
+    let restore = |frame| {
+        #[expect(unsafe_code)]
+        // SAFETY: The frame isn't mapped.
+        unsafe {
+            get_memory_frame_allocator().lock().deallocate_frame(frame);
+        }
+    };
+
+    if swap.read_slot(slot, frame).is_err() {
+        restore(frame);
+        return Some(false);
+    }
+
+    // The swap lock is held until the page is mapped, so no other fault maps it meanwhile
+    set_level_1_entry(page, PageTableEntry::set_unused);
+
+    #[expect(unsafe_code)]
+    // SAFETY: The page is part of a lazy region and isn't mapped.
+    if unsafe { map_page(page, frame, flags) }.is_err() {
+        set_level_1_entry(page, |entry| entry.set_addr(slot_addr(slot), SWAPPED));
+        restore(frame);
+        return Some(false);
+    }
+
+    swap.free_slot(slot);
+
+    Some(true)
+}
+
+/// Whether the swap is in use, see `handle_page_fault`.
+pub(super) fn is_locked() -> bool {
+    SWAP.is_locked()
+}
+
+/// Free the swap slots of the pages of `start..end` that are swapped out.
+pub(super) fn discard(start: VirtAddr, end: VirtAddr) {
+    let mut swap = SWAP.lock();
+    let Some(swap) = swap.as_mut() else {
+        return;
+    };
+
+    let mut mapper = get_memory_mapper().lock();
+    let mut addr = start;
+
+    while addr < end {
+        #[expect(unsafe_code)]
+        // SAFETY: The entry is only used while the page tables are locked.
+        match unsafe { level_1_entry(mapper.level_4_table_mut(), addr) } {
+            Ok(entry) => {
+                if let Some(slot) = swap_slot(entry) {
+                    entry.set_unused();
+                    swap.free_slot(slot);
+                }
+
+                addr += PAGE_SIZE as u64;
+            }
+            Err(skip) => addr = (addr + 1_u64).align_up(skip),
+        }
+    }
+}
+
+impl Swap {
+    /// Swap out the least recently used page of the lazy regions.
+    ///
+    /// Returns `false` when there is no page to swap out, no free slot, or it can't be written.
+    fn evict_one(&mut self) -> bool {
+        let Some(slot) = self.allocate_slot() else {
+            return false;
+        };
+
+        let regions = lazy::regions();
+        let victim = {
+            let mut mapper = get_memory_mapper().lock();
+
+            #[expect(unsafe_code)]
+            // SAFETY: The entry is only used while the page tables are locked.
+            unsafe { self.find_victim(&regions, mapper.level_4_table_mut()) }.map(
+                |(page, entry)| {
+                    let old_entry = entry.clone();
+                    entry.set_addr(slot_addr(slot), SWAPPED);
+                    tlb::flush(page.start_address());
+
+                    (page, old_entry)
+                },
+            )
+        };
+
+        let Some((page, old_entry)) = victim else {
+            self.free_slot(slot);
+            return false;
+        };
+
+        let frame = PhysFrame::containing_address(old_entry.addr());
+        if self.write_slot(slot, frame).is_err() {
+            set_level_1_entry(page, |entry| *entry = old_entry);
+            self.free_slot(slot);
+            return false;
+        }
+
+        get_memory_frame_allocator().lock().remove_mapping(frame);
+
+        true
+    }
+
+    /// Find a mapped page of `regions` that wasn't accessed since it was last scanned,
+    /// clearing the accessed flags of the pages scanned on the way (second chance).
+    ///
+    /// # Safety
+    ///
+    /// The page tables must stay locked as long as the returned entry is used.
+    #[expect(unsafe_code)]
+    unsafe fn find_victim<'table>(
+        &mut self,
+        regions: &[Option<(VirtAddr, VirtAddr)>],
+        level_4_table: &mut PageTable,
+    ) -> Option<(Page, &'table mut PageTableEntry)> {
+        let mut addr = self.hand;
+        // every page is scanned at least twice before giving up
+        let mut wraps = 0_u32;
+
+        loop {
+            // the next region from `addr`, in address order
+            let Some(&(start, _)) = regions
+                .iter()
+                .flatten()
+                .filter(|&&(_, end)| end > addr)
+                .min_by_key(|&&(start, _)| start)
+            else {
+                wraps += 1;
+                if wraps > 2 {
+                    return None;
+                }
+
+                addr = VirtAddr::zero();
+                continue;
+            };
+            addr = addr.max(start);
+
+            #[expect(unsafe_code)]
+            // SAFETY: Guaranteed by the caller.
+            match unsafe { level_1_entry(level_4_table, addr) } {
+                Ok(entry) => {
+                    let page = Page::containing_address(addr);
+                    addr += PAGE_SIZE as u64;
+
+                    let flags = entry.flags();
+                    if !flags.contains(PageTableFlags::PRESENT) {
+                        continue;
+                    }
+
+                    if flags.contains(PageTableFlags::ACCESSED) {
+                        entry.set_flags(flags - PageTableFlags::ACCESSED);
+                        tlb::flush(page.start_address());
+                        continue;
+                    }
+
+                    self.hand = addr;
+                    return Some((page, entry));
+                }
+                Err(skip) => addr = (addr + 1_u64).align_up(skip),
+            }
+        }
+    }
+
+    fn allocate_slot(&mut self) -> Option<usize> {
+        let (index, word) = self
+            .used_slots
+            .iter_mut()
+            .enumerate()
+            .find(|entry| *entry.1 != u64::MAX)?;
+
+        let bit = word.trailing_ones();
+        let slot = (index << 6_u32) + bit as usize;
+        if slot >= self.slot_count {
+            return None;
+        }
+
+        *word |= 1 << bit;
+
+        Some(slot)
+    }
+
+    fn free_slot(&mut self, slot: usize) {
+        self.used_slots[slot >> 6_u32] &= !(1 << (slot & 63));
+    }
+
+    fn read_slot(&mut self, slot: usize, frame: PhysFrame) -> Result<(), BlockError> {
+        let start = self.start_block + slot as u64 * self.blocks_per_slot;
+
+        #[expect(unsafe_code)]
+        // SAFETY: The frame was just allocated for the page.
+        let buffer = unsafe { frame_bytes(frame) };
+
+        device_io(|| self.device.read_blocks(start, buffer))
+    }
+
+    fn write_slot(&mut self, slot: usize, frame: PhysFrame) -> Result<(), BlockError> {
+        let start = self.start_block + slot as u64 * self.blocks_per_slot;
+
+        #[expect(unsafe_code)]
+        // SAFETY: The page of the frame was unmapped, so nothing else uses it.
+        let buffer = unsafe { frame_bytes(frame) };
+
+        device_io(|| self.device.write_blocks(start, buffer))
+    }
+}
+
+/// Run `io` on the swap device, which must neither allocate nor fault.
+///
+/// Swap I/O runs in the page fault handler with the swap locked, so a nested page fault
+/// can't be resolved. The fault may also come from the allocator growing the heap, with
+/// the heap locked.
+fn device_io<R>(io: impl FnOnce() -> R) -> R {
+    let nested = SWAP_IO.swap(true, Ordering::Relaxed);
+    let result = io();
+    SWAP_IO.store(nested, Ordering::Relaxed);
+
+    result
+}
+
+/// Whether the swap device is in use, see `device_io`.
+pub fn in_swap_io() -> bool {
+    SWAP_IO.load(Ordering::Relaxed)
+}
+
+/// The swap slot holding the page of `entry`, if it's swapped out.
+fn swap_slot(entry: &PageTableEntry) -> Option<usize> {
+    #[expect(clippy::cast_possible_truncation)]
+    (entry.flags() == SWAPPED)
+        .then(|| (entry.addr().as_u64() >> PAGE_SIZE.trailing_zeros()) as usize)
+}
+
+/// Address stored in the entry of a page swapped out to `slot`.
+const fn slot_addr(slot: usize) -> PhysAddr {
+    PhysAddr::new_truncate((slot as u64) << PAGE_SIZE.trailing_zeros())
+}
+
+/// Update the level 1 entry of `page` in the kernel's page tables with `update`.
+fn set_level_1_entry(page: Page, update: impl FnOnce(&mut PageTableEntry)) {
+    let mut mapper = get_memory_mapper().lock();
+
+    #[expect(unsafe_code)]
+    // SAFETY: The entry is only used while the page tables are locked.
+    if let Ok(entry) = unsafe { level_1_entry(mapper.level_4_table_mut(), page.start_address()) } {
+        update(entry);
+        tlb::flush(page.start_address());
+    }
+}
+
+/// The level 1 entry for `addr` under `level_4_table`, or when there is no level 1 table
+/// for it, the size of the range around it to skip.
+///
+/// # Safety
+///
+/// The page tables must stay locked as long as the entry is used.
+#[expect(unsafe_code)]
+unsafe fn level_1_entry<'table>(
+    level_4_table: &mut PageTable,
+    addr: VirtAddr,
+) -> Result<&'table mut PageTableEntry, u64> {
+    let next_table = |entry: &PageTableEntry, size| {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return Err(size);
+        }
+
+        #[expect(unsafe_code)]
+        // SAFETY: The entry points to a page table, and the page tables are locked.
+        Ok(unsafe { page_table::<'table>(PhysFrame::containing_address(entry.addr())) })
+    };
+
+    let level_3_table = next_table(&level_4_table[addr.p4_index()], LEVEL_4_ENTRY_SIZE)?;
+    let level_2_table = next_table(&level_3_table[addr.p3_index()], Size1GiB::SIZE)?;
+    let level_1_table = next_table(&level_2_table[addr.p2_index()], Size2MiB::SIZE)?;
+
+    Ok(&mut level_1_table[addr.p1_index()])
+}
+
+/// The contents of `frame`, through the physical memory mapping.
+///
+/// # Safety
+///
+/// Nothing else may access the frame as long as the slice is used.
+#[expect(unsafe_code)]
+unsafe fn frame_bytes<'frame>(frame: PhysFrame) -> &'frame mut [u8] {
+    let virt = *PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("Memory Offset wasn't initialized yet")
+        + frame.start_address().as_u64();
+
+    #[expect(unsafe_code)]
+    // SAFETY: Guaranteed by the caller, and the frame is mapped at the offset.
+    unsafe {
+        core::slice::from_raw_parts_mut(virt.as_mut_ptr::<u8>(), PAGE_SIZE)
+    }
+}
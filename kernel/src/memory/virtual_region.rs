@@ -0,0 +1,97 @@
+/// Maximum number of disjoint free ranges that can be tracked.
+const MAX_FREE_RANGES: usize = 64;
+
+#[derive(Clone, Copy)]
+struct FreeRange {
+    start: usize,
+    end: usize,
+}
+
+/// Allocator of virtual address ranges, it doesn't map anything itself.
+///
+/// It can't allocate, so the free ranges are kept in a fixed array. Freeing a range
+/// that can neither be merged nor stored leaks it.
+pub struct RegionAllocator {
+    /// Free ranges sorted by their start address, the first `free_ranges_len` are valid.
+    free_ranges: [FreeRange; MAX_FREE_RANGES],
+    free_ranges_len: usize,
+}
+
+impl RegionAllocator {
+    /// Creates an empty ``RegionAllocator``.
+    pub const fn new() -> Self {
+        Self {
+            free_ranges: [FreeRange { start: 0, end: 0 }; MAX_FREE_RANGES],
+            free_ranges_len: 0,
+        }
+    }
+
+    /// Remove `size` bytes aligned to `align` (a power of 2) from the first free range
+    /// large enough to hold them.
+    pub fn allocate(&mut self, size: usize, align: usize) -> Option<usize> {
+        let index = self.free_ranges[..self.free_ranges_len]
+            .iter()
+            .position(|r| {
+                align_up(r.start, align)
+                    .checked_add(size)
+                    .is_some_and(|end| end <= r.end)
+            })?;
+
+        let range = self.free_ranges[index];
+        let start = align_up(range.start, align);
+
+        self.free_ranges
+            .copy_within(index + 1..self.free_ranges_len, index);
+        self.free_ranges_len -= 1;
+
+        // give back what's left around the allocation
+        if start > range.start {
+            self.deallocate(range.start, start - range.start);
+        }
+        if start + size < range.end {
+            self.deallocate(start + size, range.end - (start + size));
+        }
+
+        Some(start)
+    }
+
+    /// Give a range back, merging it with its neighbours.
+    ///
+    /// Also used to add the initial ranges, which must not overlap.
+    pub fn deallocate(&mut self, start: usize, size: usize) {
+        let end = start + size;
+        let index = self.free_ranges[..self.free_ranges_len].partition_point(|r| r.end <= start);
+
+        let merges_prev = index > 0 && self.free_ranges[index - 1].end == start;
+        let merges_next = index < self.free_ranges_len && self.free_ranges[index].start == end;
+
+        match (merges_prev, merges_next) {
+            (true, true) => {
+                self.free_ranges[index - 1].end = self.free_ranges[index].end;
+                self.free_ranges
+                    .copy_within(index + 1..self.free_ranges_len, index);
+                self.free_ranges_len -= 1;
+            }
+            (true, false) => self.free_ranges[index - 1].end = end,
+            (false, true) => self.free_ranges[index].start = start,
+            (false, false) => {
+                if self.free_ranges_len == MAX_FREE_RANGES {
+                    // NOTE: The range is leaked, the address space is large enough to afford it.
+                    return;
+                }
+
+                self.free_ranges
+                    .copy_within(index..self.free_ranges_len, index + 1);
+                self.free_ranges[index] = FreeRange { start, end };
+                self.free_ranges_len += 1;
+            }
+        }
+    }
+}
+
+/// Align the given address `addr` upwards to alignment `align`.
+///
+/// Requires that `align` is a power of two.
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
@@ -0,0 +1,337 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use spin::{once::Once, Mutex};
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{
+    dbg_println,
+    drivers::{hpet, pit},
+    interrupts::apic::local::LAPIC,
+};
+
+/// How long the LAPIC timer and the TSC are measured against the HPET or the PIT.
+const CALIBRATION_MS: u32 = 50;
+
+/// Frequency of the tick until it's changed with `set_tick_hz`.
+pub const DEFAULT_TICK_HZ: u32 = 100;
+
+/// Timers pending at most at once, more sleeping tasks poll the tick count instead.
+const MAX_TIMERS: usize = 64;
+
+/// How the kernel tick is driven.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickMode {
+    /// The timer interrupt fires on every tick, and increments the tick count.
+    Periodic,
+    /// The timer interrupt only fires when a timer expires, and the tick count follows
+    /// the TSC.
+    Tickless,
+}
+
+/// Frequencies of the LAPIC timer and the TSC.
+#[derive(Debug, Clone, Copy)]
+struct Calibration {
+    timer_hz: u64,
+    tsc_hz: u64,
+    /// Whether the LAPIC timer supports the TSC-deadline mode.
+    tsc_deadline: bool,
+}
+
+static CALIBRATION: Once<Calibration> = Once::new();
+
+/// The tick count in periodic mode.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+static CLOCK: Mutex<Clock> = Mutex::new(Clock {
+    mode: TickMode::Periodic,
+    hz: DEFAULT_TICK_HZ,
+    base_ticks: 0,
+    base_tsc: 0,
+});
+
+struct Clock {
+    mode: TickMode,
+    hz: u32,
+    /// The tick count and TSC when the tickless mode started.
+    base_ticks: u64,
+    base_tsc: u64,
+}
+
+impl Clock {
+    fn ticks(&self) -> u64 {
+        match self.mode {
+            TickMode::Periodic => TICKS.load(Ordering::Relaxed),
+            TickMode::Tickless => {
+                let elapsed = rdtsc().saturating_sub(self.base_tsc);
+
+                self.base_ticks + convert(elapsed, calibration().tsc_hz, u64::from(self.hz))
+            }
+        }
+    }
+
+    /// Switch to `mode` at `hz`, carrying the tick count over.
+    fn reconfigure(&mut self, mode: TickMode, hz: u32) {
+        let ticks = convert(self.ticks(), u64::from(self.hz), u64::from(hz));
+
+        self.mode = mode;
+        self.hz = hz;
+        self.base_ticks = ticks;
+        self.base_tsc = rdtsc();
+        TICKS.store(ticks, Ordering::Relaxed);
+
+        match mode {
+            TickMode::Periodic => {
+                let period = convert(1, u64::from(hz), calibration().timer_hz);
+
+                LAPIC
+                    .lock()
+                    .start_periodic_timer(u32::try_from(period).unwrap_or(u32::MAX).max(1));
+            }
+            TickMode::Tickless => self.arm(&TIMERS.lock()[..]),
+        }
+    }
+
+    /// Program the timer interrupt for the earliest of `timers`, in tickless mode.
+    fn arm(&self, timers: &[Option<Timer>]) {
+        let Some(deadline) = timers.iter().flatten().map(|timer| timer.deadline).min() else {
+            LAPIC.lock().stop_timer();
+            return;
+        };
+        let calibration = calibration();
+
+        if calibration.tsc_deadline {
+            let ticks = deadline.saturating_sub(self.base_ticks);
+            let tsc = self.base_tsc + convert(ticks, u64::from(self.hz), calibration.tsc_hz);
+
+            LAPIC.lock().start_tsc_deadline_timer(tsc);
+        } else {
+            // the timer is re-armed when the deadline is too far to be reached at once
+            let ticks = deadline.saturating_sub(self.ticks());
+            let count = convert(ticks, u64::from(self.hz), calibration.timer_hz);
+
+            LAPIC
+                .lock()
+                .start_one_shot_timer(u32::try_from(count).unwrap_or(u32::MAX).max(1));
+        }
+    }
+}
+
+/// Convert `value` counted at `from` Hz to `to` Hz.
+#[expect(clippy::integer_division)]
+#[expect(clippy::cast_possible_truncation)]
+fn convert(value: u64, from: u64, to: u64) -> u64 {
+    (u128::from(value) * u128::from(to) / u128::from(from)).min(u128::from(u64::MAX)) as u64
+}
+
+fn rdtsc() -> u64 {
+    #[expect(unsafe_code)]
+    // SAFETY: Only reads the time stamp counter.
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+}
+
+fn calibration() -> &'static Calibration {
+    CALIBRATION.get().expect("Time wasn't initialized yet")
+}
+
+/// Calibrate the LAPIC timer and the TSC against the HPET, or the PIT when there is none,
+/// and start the periodic tick.
+///
+/// Must be called with interrupts disabled, after the LAPIC and the HPET are initialized.
+pub(crate) fn init() {
+    let hpet = hpet::get();
+
+    let mut tsc = 0;
+    let timer = LAPIC.lock().measure_timer(|| {
+        let start = rdtsc();
+        match hpet {
+            Some(hpet) => hpet.wait_ns(u64::from(CALIBRATION_MS) * 1_000_000),
+            None => pit::wait_ms(CALIBRATION_MS),
+        }
+        tsc = rdtsc() - start;
+    });
+
+    let calibration = Calibration {
+        timer_hz: convert(u64::from(timer), u64::from(CALIBRATION_MS), 1000),
+        tsc_hz: convert(tsc, u64::from(CALIBRATION_MS), 1000),
+        // CPUID.01H:ECX.TSC_Deadline
+        tsc_deadline: core::arch::x86_64::__cpuid(1).ecx & (1_u32 << 24_u32) != 0,
+    };
+    dbg_println!(
+        "LAPIC timer: {} Hz, TSC: {} Hz (calibrated against the {}){}",
+        calibration.timer_hz,
+        calibration.tsc_hz,
+        if hpet.is_some() { "HPET" } else { "PIT" },
+        if calibration.tsc_deadline {
+            ", TSC-deadline supported"
+        } else {
+            ""
+        }
+    );
+    CALIBRATION.call_once(|| calibration);
+
+    CLOCK
+        .lock()
+        .reconfigure(TickMode::Periodic, DEFAULT_TICK_HZ);
+}
+
+/// Ticks since boot, at `tick_hz`. It never goes backwards.
+#[must_use]
+pub fn ticks() -> u64 {
+    without_interrupts(|| CLOCK.lock().ticks())
+}
+
+#[must_use]
+pub fn tick_hz() -> u32 {
+    without_interrupts(|| CLOCK.lock().hz)
+}
+
+#[must_use]
+pub fn tick_mode() -> TickMode {
+    without_interrupts(|| CLOCK.lock().mode)
+}
+
+/// Tick at `hz`, converting the tick count.
+///
+/// # Panics
+///
+/// When `hz` is 0.
+pub fn set_tick_hz(hz: u32) {
+    assert!(hz > 0, "The tick frequency must not be 0");
+
+    without_interrupts(|| {
+        let mut clock = CLOCK.lock();
+        let mode = clock.mode;
+
+        clock.reconfigure(mode, hz);
+    });
+}
+
+/// Switch between the periodic and the tickless tick.
+pub fn set_tick_mode(mode: TickMode) {
+    without_interrupts(|| {
+        let mut clock = CLOCK.lock();
+        let hz = clock.hz;
+
+        clock.reconfigure(mode, hz);
+    });
+}
+
+/// Called by the LAPIC timer interrupt handler: advance the tick and wake the expired
+/// timers.
+pub(crate) fn on_timer_interrupt() {
+    if !CALIBRATION.is_completed() {
+        return;
+    }
+
+    let clock = CLOCK.lock();
+    let now = match clock.mode {
+        TickMode::Periodic => TICKS.fetch_add(1, Ordering::Relaxed) + 1,
+        TickMode::Tickless => clock.ticks(),
+    };
+
+    let mut timers = TIMERS.lock();
+    for slot in timers.iter_mut() {
+        if slot.as_ref().is_some_and(|timer| timer.deadline <= now) {
+            if let Some(timer) = slot.take() {
+                timer.waker.wake();
+            }
+        }
+    }
+
+    if clock.mode == TickMode::Tickless {
+        clock.arm(&timers[..]);
+    }
+}
+
+/// A task waiting for a deadline.
+struct Timer {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+}
+
+static TIMERS: Mutex<[Option<Timer>; MAX_TIMERS]> = Mutex::new([const { None }; MAX_TIMERS]);
+
+/// Wait for `ticks` ticks.
+#[must_use]
+pub fn sleep(ticks: u64) -> Sleep {
+    sleep_until(self::ticks().saturating_add(ticks))
+}
+
+/// Wait until the tick count reaches `deadline`.
+#[must_use]
+pub fn sleep_until(deadline: u64) -> Sleep {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    Sleep {
+        deadline,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+    }
+}
+
+/// A future completing at a tick deadline, see `sleep`.
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: u64,
+    id: u64,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        without_interrupts(|| {
+            let clock = CLOCK.lock();
+
+            if clock.ticks() >= self.deadline {
+                return Poll::Ready(());
+            }
+
+            let mut timers = TIMERS.lock();
+            let slot = timers
+                .iter()
+                .position(|slot| slot.as_ref().is_some_and(|timer| timer.id == self.id))
+                .or_else(|| timers.iter().position(Option::is_none));
+
+            let Some(slot) = slot else {
+                // no timer left, poll again
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            };
+
+            timers[slot] = Some(Timer {
+                id: self.id,
+                deadline: self.deadline,
+                waker: cx.waker().clone(),
+            });
+
+            if clock.mode == TickMode::Tickless {
+                clock.arm(&timers[..]);
+            }
+
+            Poll::Pending
+        })
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+
+            if let Some(slot) = timers
+                .iter_mut()
+                .find(|slot| slot.as_ref().is_some_and(|timer| timer.id == self.id))
+            {
+                *slot = None;
+            }
+        });
+    }
+}